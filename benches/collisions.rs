@@ -0,0 +1,64 @@
+//! Weak hash collision rates of the rolling hashes on the sample files.
+//! Run with `cargo bench --bench collisions`.
+
+use rustdiff::algorithms::registry::RollingHashKind;
+use std::collections::{HashMap, HashSet};
+use std::fs::read;
+use std::time::Instant;
+
+const SAMPLE_FILES: [&str; 2] = ["data/sample.txt", "data/sample_modified.txt"];
+const CHUNK_SIZES: [usize; 5] = [4, 8, 16, 32, 64];
+
+fn main() {
+    println!(
+        "{:<26} {:>6} {:<12} {:>8} {:>10} {:>8} {:>10}",
+        "file", "chunk", "algorithm", "windows", "colliding", "rate", "time"
+    );
+    for path in SAMPLE_FILES {
+        let data = read(path).unwrap();
+        for chunk_size in CHUNK_SIZES {
+            for kind in RollingHashKind::ALL {
+                let start = Instant::now();
+                let (windows, colliding) = count_collisions(&data, chunk_size, kind);
+                let elapsed = start.elapsed();
+                println!(
+                    "{:<26} {:>6} {:<12} {:>8} {:>10} {:>7.3}% {:>10.2?}",
+                    path,
+                    chunk_size,
+                    kind.name(),
+                    windows,
+                    colliding,
+                    colliding as f64 * 100.0 / windows as f64,
+                    elapsed
+                );
+            }
+        }
+    }
+}
+
+/// Roll the hash over every offset of the data. Returns the number of
+/// distinct windows and how many of them share their weak hash with a
+/// window that has different contents.
+fn count_collisions(data: &[u8], chunk_size: usize, kind: RollingHashKind) -> (usize, usize) {
+    let mut algo = kind.create();
+    let mut windows: HashMap<u32, HashSet<&[u8]>> = HashMap::new();
+
+    let weak_hash = algo.get_chunk_hash(&data[..chunk_size]).unwrap();
+    windows
+        .entry(weak_hash)
+        .or_default()
+        .insert(&data[..chunk_size]);
+    for (offset, byte) in data[chunk_size..].iter().enumerate() {
+        let weak_hash = algo.get_rolling_hash(byte).unwrap();
+        let window = &data[offset + 1..offset + 1 + chunk_size];
+        windows.entry(weak_hash).or_default().insert(window);
+    }
+
+    let distinct = windows.values().map(|windows| windows.len()).sum();
+    let colliding = windows
+        .values()
+        .filter(|windows| windows.len() > 1)
+        .map(|windows| windows.len())
+        .sum();
+    (distinct, colliding)
+}
@@ -0,0 +1,60 @@
+//! Patch time across file sizes. Blocks are located from their index, so
+//! the throughput should stay flat as the file and its block count grow.
+//! Run with `cargo bench --bench patch`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rustdiff::chunking::Chunking;
+use rustdiff::delta::gen_delta;
+use rustdiff::patch::patch_from_reader;
+use rustdiff::sign::{SignMode, Signature};
+use rustdiff::utils::splitmix64;
+use std::io::Cursor;
+
+const FILE_SIZES: [usize; 4] = [64 * 1024, 256 * 1024, 1024 * 1024, 4 * 1024 * 1024];
+const CHUNK_SIZE: usize = 64;
+/// Distance between two edits of the modified file
+const EDIT_EVERY: usize = 16 * 1024;
+
+fn patch(c: &mut Criterion) {
+    for chunking in [Chunking::Fixed, Chunking::gear(CHUNK_SIZE)] {
+        let name = match chunking {
+            Chunking::Fixed => "fixed",
+            Chunking::Gear { .. } => "cdc",
+        };
+        let mut group = c.benchmark_group(format!("patch/{}", name));
+        group.sample_size(20);
+        for size in FILE_SIZES {
+            let mut state = size as u64;
+            let data: Vec<u8> = (0..size).map(|_| splitmix64(&mut state) as u8).collect();
+            let mut modified = data.clone();
+            for offset in (EDIT_EVERY / 2..size).step_by(EDIT_EVERY) {
+                modified[offset..offset + 8].copy_from_slice(b"modified");
+            }
+
+            let (signatures, _) = Signature::gen_sigs_chunked(
+                data.as_slice(),
+                CHUNK_SIZE,
+                chunking,
+                "adler",
+                "xxh3-128",
+                SignMode::Lean,
+            )
+            .unwrap();
+            let mut delta = Vec::new();
+            gen_delta(modified.as_slice(), &mut delta, &signatures).unwrap();
+
+            group.throughput(Throughput::Bytes(size as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+                b.iter(|| {
+                    let mut output = Vec::with_capacity(modified.len());
+                    patch_from_reader(Cursor::new(&data), delta.as_slice(), &mut output).unwrap();
+                    output
+                });
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, patch);
+criterion_main!(benches);
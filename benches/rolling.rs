@@ -0,0 +1,43 @@
+//! Throughput of the rolling hashes across chunk sizes.
+//! Run with `cargo bench --bench rolling`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rustdiff::algorithms::registry::RollingHashKind;
+use rustdiff::utils::splitmix64;
+use std::hint::black_box;
+
+const DATA_LEN: usize = 1024 * 1024;
+const CHUNK_SIZES: [usize; 5] = [4, 64, 1024, 16 * 1024, 64 * 1024];
+
+fn rolling(c: &mut Criterion) {
+    let mut state = 0;
+    let data: Vec<u8> = (0..DATA_LEN)
+        .map(|_| splitmix64(&mut state) as u8)
+        .collect();
+
+    for kind in RollingHashKind::ALL {
+        let mut group = c.benchmark_group(format!("rolling/{}", kind));
+        group.sample_size(20);
+        for chunk_size in CHUNK_SIZES {
+            // Only the bytes rolled in count, not the first window
+            group.throughput(Throughput::Bytes((DATA_LEN - chunk_size) as u64));
+            group.bench_with_input(
+                BenchmarkId::from_parameter(chunk_size),
+                &chunk_size,
+                |b, &chunk_size| {
+                    let mut algo = kind.create();
+                    b.iter(|| {
+                        algo.get_chunk_hash(&data[..chunk_size]).unwrap();
+                        for byte in &data[chunk_size..] {
+                            black_box(algo.get_rolling_hash(byte).unwrap());
+                        }
+                    });
+                },
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, rolling);
+criterion_main!(benches);
@@ -1,4 +1,8 @@
 use crate::error::DiffError;
+use std::collections::BTreeMap;
+
+/// Named numeric settings of a rolling hash, such as a base or a seed
+pub type AlgorithmParams = BTreeMap<String, u64>;
 
 pub trait Algorithm {
     fn get_chunk_hash(&mut self, chunk: &[u8]) -> Result<u32, DiffError>;
@@ -7,5 +11,11 @@ pub trait Algorithm {
 
     fn get_current_hash(&self) -> Result<u32, DiffError>;
 
-    fn get_current_window(&self) -> Result<&Vec<u8>, DiffError>;
+    /// The bytes the hash currently covers, from oldest to newest
+    fn get_current_window(&self) -> Result<&[u8], DiffError>;
+
+    /// Settings needed to build the same hash again, recorded in the headers
+    fn params(&self) -> AlgorithmParams {
+        AlgorithmParams::new()
+    }
 }
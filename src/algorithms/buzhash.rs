@@ -0,0 +1,142 @@
+use crate::algorithms::algorithm::{Algorithm, AlgorithmParams};
+use crate::algorithms::window::RingWindow;
+use crate::error::DiffError;
+use crate::utils::splitmix64;
+
+pub const DEFAULT_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Cyclic polynomial rolling hash. Every byte is swapped for a random word
+/// from a table built from `seed`, and the words are combined by rotating
+/// and xoring.
+pub struct Buzhash {
+    seed: u64,
+    table: [u32; 256],
+    hash: u32,
+    current_window: RingWindow,
+}
+
+impl Buzhash {
+    pub fn new() -> Self {
+        Buzhash::with_seed(DEFAULT_SEED)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Buzhash {
+            seed,
+            table: gen_table(seed),
+            hash: 0,
+            current_window: RingWindow::new(),
+        }
+    }
+
+    /// Build from a `seed` param, the default seed is used when missing
+    pub fn from_params(params: &AlgorithmParams) -> Result<Self, DiffError> {
+        if let Some(key) = params.keys().find(|key| key.as_str() != "seed") {
+            return Err(DiffError::AlgorithmParams(format!(
+                "buzhash doesn't take {}",
+                key
+            )));
+        }
+        let seed = params.get("seed").copied().unwrap_or(DEFAULT_SEED);
+        Ok(Buzhash::with_seed(seed))
+    }
+}
+
+impl Default for Buzhash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Algorithm for Buzhash {
+    fn get_chunk_hash(&mut self, chunk: &[u8]) -> Result<u32, DiffError> {
+        self.hash = 0;
+        for byte in chunk {
+            self.hash = self.hash.rotate_left(1) ^ self.table[*byte as usize];
+        }
+
+        self.current_window.fill(chunk);
+
+        let hash = self.get_current_hash()?;
+        Ok(hash)
+    }
+
+    fn get_rolling_hash(&mut self, new_byte: &u8) -> Result<u32, DiffError> {
+        // The byte leaving has been rotated once for every byte after it
+        let size = self.current_window.len() as u32;
+        let last_byte = self.current_window.roll(*new_byte);
+        self.hash = self.hash.rotate_left(1)
+            ^ self.table[last_byte as usize].rotate_left(size)
+            ^ self.table[*new_byte as usize];
+
+        let hash = self.get_current_hash()?;
+        Ok(hash)
+    }
+
+    fn get_current_hash(&self) -> Result<u32, DiffError> {
+        Ok(self.hash)
+    }
+
+    fn get_current_window(&self) -> Result<&[u8], DiffError> {
+        Ok(self.current_window.as_slice())
+    }
+
+    fn params(&self) -> AlgorithmParams {
+        AlgorithmParams::from([("seed".to_string(), self.seed)])
+    }
+}
+
+/// Fill the substitution table from a splitmix64 sequence, so the same seed
+/// always gives the same table
+fn gen_table(seed: u64) -> [u32; 256] {
+    let mut state = seed;
+    let mut table = [0; 256];
+    for entry in table.iter_mut() {
+        *entry = (splitmix64(&mut state) >> 32) as u32;
+    }
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_chunk_hash() {
+        let mut buzhash = Buzhash::new();
+        let table = gen_table(DEFAULT_SEED);
+        let hash = buzhash.get_chunk_hash(b"ab").unwrap();
+        let answer = table[b'a' as usize].rotate_left(1) ^ table[b'b' as usize];
+        assert_eq!(hash, answer);
+    }
+
+    #[test]
+    fn test_get_rolling_hash() {
+        let data = "hello world, hello again, and again and again".as_bytes();
+        // Sizes around the 32 bit rotation wrap
+        for size in [1, 5, 31, 32, 33] {
+            let mut buzhash = Buzhash::new();
+            buzhash.get_chunk_hash(&data[..size]).unwrap();
+            for (offset, byte) in data[size..].iter().enumerate() {
+                let hash = buzhash.get_rolling_hash(byte).unwrap();
+                let window = &data[offset + 1..offset + 1 + size];
+                let answer = Buzhash::new().get_chunk_hash(window).unwrap();
+                assert_eq!(hash, answer);
+            }
+        }
+    }
+
+    #[test]
+    fn test_seed() {
+        let chunk = "hello world".as_bytes();
+        let hash = Buzhash::with_seed(7).get_chunk_hash(chunk).unwrap();
+        assert_eq!(hash, Buzhash::with_seed(7).get_chunk_hash(chunk).unwrap());
+        assert_ne!(hash, Buzhash::with_seed(8).get_chunk_hash(chunk).unwrap());
+
+        let params = AlgorithmParams::from([("seed".to_string(), 7)]);
+        let buzhash = Buzhash::from_params(&params).unwrap();
+        assert_eq!(buzhash.params(), params);
+        let params = AlgorithmParams::from([("base".to_string(), 7)]);
+        assert!(Buzhash::from_params(&params).is_err());
+    }
+}
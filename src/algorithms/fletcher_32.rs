@@ -1,12 +1,13 @@
 use crate::error::DiffError;
 use super::algorithm::Algorithm;
+use super::window::RingWindow;
 
 const MOD: u32 = 65535;
 
 pub struct Fletcher32 {
     a: u32,
     b: u32,
-    current_window: Vec<u8>,
+    current_window: RingWindow,
 }
 
 impl Fletcher32 {
@@ -14,20 +15,29 @@ impl Fletcher32 {
         Fletcher32 {
             a: 0,
             b: 0,
-            current_window: Vec::new(),
+            current_window: RingWindow::new(),
         }
     }
 }
 
+impl Default for Fletcher32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Algorithm for Fletcher32 {
     fn get_chunk_hash(&mut self, chunk: &[u8]) -> Result<u32, DiffError> {
+        // Start over, the hasher may be reused for another window
+        self.a = 0;
+        self.b = 0;
         for byte in chunk {
             let current_byte = *byte as u32;
             self.a = (self.a + current_byte) % MOD;
             self.b = (self.b + self.a) % MOD;
         }
 
-        self.current_window = chunk.to_vec();
+        self.current_window.fill(chunk);
 
         let hash = self.get_current_hash()?;
         Ok(hash)
@@ -39,29 +49,25 @@ impl Algorithm for Fletcher32 {
     }
 
     fn get_rolling_hash(&mut self, new_byte: &u8) -> Result<u32, DiffError> {
-        // Add a byte
-        self.a = (self.a + *new_byte as u32) % MOD;
-        self.b = (self.b + self.a) % MOD;
-        self.current_window.push(new_byte.clone());
-
-        // Remove a byte
-        let last_byte = self.current_window[0] as u32;
-        let size = self.current_window.len() as u32;
-        self.a = (self.a - last_byte) % MOD;
-        self.b = (self.b - (size * last_byte as u32)) % MOD;
-        self.current_window.remove(0);
+        let size = self.current_window.len() as u64;
+        let last_byte = self.current_window.roll(*new_byte) as u32;
+
+        // Swap the bytes, adding MOD first keeps the sums from going below zero
+        self.a = (self.a + MOD - last_byte + *new_byte as u32) % MOD;
+        // The byte leaving was counted once for every byte in the window
+        let removed = (size * last_byte as u64 % MOD as u64) as u32;
+        self.b = (self.b + self.a + MOD - removed) % MOD;
 
         let hash = self.get_current_hash()?;
         Ok(hash)
     }
 
-    fn get_current_window(&self) -> Result<&Vec<u8>, DiffError> {
-        Ok(&self.current_window)
+    fn get_current_window(&self) -> Result<&[u8], DiffError> {
+        Ok(self.current_window.as_slice())
     }
 }
 
 #[cfg(test)]
-
 mod test {
 
     use super::*;
@@ -84,14 +90,28 @@ mod test {
         let answer = 436208732;
         assert_eq!(hash, answer);
         // Move the window and check
-        let new_byte = 'a' as u8;
+        let new_byte = b'a';
         let hash = adler.get_rolling_hash(&new_byte).unwrap();
         let answer = 433914965;
         assert_eq!(hash, answer);
         // Move the window and check
-        let new_byte = 'm' as u8;
+        let new_byte = b'm';
         let hash = adler.get_rolling_hash(&new_byte).unwrap();
         let answer = 434308189;
         assert_eq!(hash, answer);
     }
+
+    #[test]
+    fn test_get_rolling_hash_wraps() {
+        // Zeros rolling in over 0xff bytes take the sums below what's removed
+        let mut data = vec![0xff; 300];
+        data.extend([0; 300]);
+        let mut hasher = Fletcher32::new();
+        hasher.get_chunk_hash(&data[..300]).unwrap();
+        for (offset, byte) in data[300..].iter().enumerate() {
+            let hash = hasher.get_rolling_hash(byte).unwrap();
+            let window = &data[offset + 1..offset + 301];
+            assert_eq!(hash, Fletcher32::new().get_chunk_hash(window).unwrap());
+        }
+    }
 }
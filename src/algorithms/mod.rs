@@ -1,3 +1,8 @@
 pub mod algorithm;
 pub mod adler_32;
-pub mod fletcher_32;
\ No newline at end of file
+pub mod buzhash;
+pub mod fletcher_32;
+pub mod rabin_karp;
+pub mod registry;
+pub mod strong_hash;
+pub mod window;
\ No newline at end of file
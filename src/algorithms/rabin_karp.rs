@@ -0,0 +1,163 @@
+use crate::algorithms::algorithm::{Algorithm, AlgorithmParams};
+use crate::algorithms::window::RingWindow;
+use crate::error::DiffError;
+
+/// Largest prime below 2^32, so the hash uses the whole u32 range
+pub const DEFAULT_MODULUS: u64 = 4294967291;
+pub const DEFAULT_BASE: u64 = 257;
+
+/// Polynomial rolling hash, the window is read as a number in `base`
+/// modulo `modulus`
+pub struct RabinKarp {
+    base: u64,
+    modulus: u64,
+    hash: u64,
+    /// base^(window length - 1), the weight of the byte leaving the window
+    power: u64,
+    current_window: RingWindow,
+}
+
+impl RabinKarp {
+    pub fn new() -> Self {
+        RabinKarp {
+            base: DEFAULT_BASE,
+            modulus: DEFAULT_MODULUS,
+            hash: 0,
+            power: 0,
+            current_window: RingWindow::new(),
+        }
+    }
+
+    /// The modulus has to fit the hash in a u32 and the base has to be
+    /// smaller than the modulus
+    pub fn with_params(base: u64, modulus: u64) -> Result<Self, DiffError> {
+        if !(2..=1 << 32).contains(&modulus) {
+            return Err(DiffError::AlgorithmParams(format!(
+                "modulus {} has to be between 2 and 2^32",
+                modulus
+            )));
+        }
+        if !(2..modulus).contains(&base) {
+            return Err(DiffError::AlgorithmParams(format!(
+                "base {} has to be between 2 and the modulus",
+                base
+            )));
+        }
+        Ok(RabinKarp {
+            base,
+            modulus,
+            ..RabinKarp::new()
+        })
+    }
+
+    /// Build from `base` and `modulus` params, missing ones use the defaults
+    pub fn from_params(params: &AlgorithmParams) -> Result<Self, DiffError> {
+        if let Some(key) = params
+            .keys()
+            .find(|key| !["base", "modulus"].contains(&key.as_str()))
+        {
+            return Err(DiffError::AlgorithmParams(format!(
+                "rabin-karp doesn't take {}",
+                key
+            )));
+        }
+        let base = params.get("base").copied().unwrap_or(DEFAULT_BASE);
+        let modulus = params.get("modulus").copied().unwrap_or(DEFAULT_MODULUS);
+        RabinKarp::with_params(base, modulus)
+    }
+}
+
+impl Default for RabinKarp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Algorithm for RabinKarp {
+    fn get_chunk_hash(&mut self, chunk: &[u8]) -> Result<u32, DiffError> {
+        self.hash = 0;
+        self.power = 1;
+        for (index, byte) in chunk.iter().enumerate() {
+            self.hash = (self.hash * self.base + *byte as u64) % self.modulus;
+            if index > 0 {
+                self.power = self.power * self.base % self.modulus;
+            }
+        }
+
+        self.current_window.fill(chunk);
+
+        let hash = self.get_current_hash()?;
+        Ok(hash)
+    }
+
+    fn get_rolling_hash(&mut self, new_byte: &u8) -> Result<u32, DiffError> {
+        // Remove a byte
+        let last_byte = self.current_window.roll(*new_byte) as u64;
+        let weight = last_byte * self.power % self.modulus;
+        self.hash = (self.hash + self.modulus - weight) % self.modulus;
+
+        // Add a byte
+        self.hash = (self.hash * self.base + *new_byte as u64) % self.modulus;
+
+        let hash = self.get_current_hash()?;
+        Ok(hash)
+    }
+
+    fn get_current_hash(&self) -> Result<u32, DiffError> {
+        Ok(self.hash as u32)
+    }
+
+    fn get_current_window(&self) -> Result<&[u8], DiffError> {
+        Ok(self.current_window.as_slice())
+    }
+
+    fn params(&self) -> AlgorithmParams {
+        AlgorithmParams::from([
+            ("base".to_string(), self.base),
+            ("modulus".to_string(), self.modulus),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_chunk_hash() {
+        // 'a' * 31^2 + 'b' * 31 + 'c'
+        let mut rabin_karp = RabinKarp::with_params(31, 1_000_003).unwrap();
+        let hash = rabin_karp.get_chunk_hash(b"abc").unwrap();
+        let answer = 97 * 31 * 31 + 98 * 31 + 99;
+        assert_eq!(hash, answer);
+    }
+
+    #[test]
+    fn test_get_rolling_hash() {
+        let data = "hello world, hello again".as_bytes();
+        let size = 11;
+        let mut rabin_karp = RabinKarp::new();
+        rabin_karp.get_chunk_hash(&data[..size]).unwrap();
+        for (offset, byte) in data[size..].iter().enumerate() {
+            let hash = rabin_karp.get_rolling_hash(byte).unwrap();
+            let window = &data[offset + 1..offset + 1 + size];
+            let answer = RabinKarp::new().get_chunk_hash(window).unwrap();
+            assert_eq!(hash, answer);
+            assert_eq!(rabin_karp.get_current_window().unwrap(), window);
+        }
+    }
+
+    #[test]
+    fn test_params() {
+        let params = AlgorithmParams::from([("base".to_string(), 31)]);
+        let rabin_karp = RabinKarp::from_params(&params).unwrap();
+        assert_eq!(rabin_karp.params()["base"], 31);
+        assert_eq!(rabin_karp.params()["modulus"], DEFAULT_MODULUS);
+
+        assert!(RabinKarp::with_params(1, 65521).is_err());
+        assert!(RabinKarp::with_params(65521, 65521).is_err());
+        assert!(RabinKarp::with_params(257, (1 << 32) + 1).is_err());
+        let params = AlgorithmParams::from([("seed".to_string(), 1)]);
+        assert!(RabinKarp::from_params(&params).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+use crate::algorithms::adler_32::Adler32;
+use crate::algorithms::algorithm::{Algorithm, AlgorithmParams};
+use crate::algorithms::buzhash::Buzhash;
+use crate::algorithms::fletcher_32::Fletcher32;
+use crate::algorithms::rabin_karp::RabinKarp;
+use crate::error::DiffError;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{OnceLock, RwLock};
+
+/// Builds a fresh rolling hash from its params
+pub type AlgorithmFactory = fn(&AlgorithmParams) -> Result<Box<dyn Algorithm>, DiffError>;
+
+/// Rolling hashes that ship with the crate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RollingHashKind {
+    Adler32,
+    Fletcher32,
+    RabinKarp,
+    Buzhash,
+}
+
+impl RollingHashKind {
+    pub const ALL: [RollingHashKind; 4] = [
+        RollingHashKind::Adler32,
+        RollingHashKind::Fletcher32,
+        RollingHashKind::RabinKarp,
+        RollingHashKind::Buzhash,
+    ];
+
+    /// Name the algorithm is registered under and recorded with in headers
+    pub fn name(&self) -> &'static str {
+        match self {
+            RollingHashKind::Adler32 => "adler",
+            RollingHashKind::Fletcher32 => "fletcher",
+            RollingHashKind::RabinKarp => "rabin-karp",
+            RollingHashKind::Buzhash => "buzhash",
+        }
+    }
+
+    /// Build the algorithm with its default params
+    pub fn create(&self) -> Box<dyn Algorithm> {
+        match self {
+            RollingHashKind::Adler32 => Box::new(Adler32::new()),
+            RollingHashKind::Fletcher32 => Box::new(Fletcher32::new()),
+            RollingHashKind::RabinKarp => Box::new(RabinKarp::new()),
+            RollingHashKind::Buzhash => Box::new(Buzhash::new()),
+        }
+    }
+
+    pub fn create_with(&self, params: &AlgorithmParams) -> Result<Box<dyn Algorithm>, DiffError> {
+        match self {
+            RollingHashKind::RabinKarp => Ok(Box::new(RabinKarp::from_params(params)?)),
+            RollingHashKind::Buzhash => Ok(Box::new(Buzhash::from_params(params)?)),
+            _ if params.is_empty() => Ok(self.create()),
+            _ => Err(DiffError::AlgorithmParams(format!(
+                "{} doesn't take any params",
+                self.name()
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for RollingHashKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for RollingHashKind {
+    type Err = DiffError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        RollingHashKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or_else(|| DiffError::UnknownAlgorithm(name.to_string()))
+    }
+}
+
+fn registry() -> &'static RwLock<BTreeMap<String, AlgorithmFactory>> {
+    static REGISTRY: OnceLock<RwLock<BTreeMap<String, AlgorithmFactory>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let builtins: [(RollingHashKind, AlgorithmFactory); 4] = [
+            (RollingHashKind::Adler32, |params| {
+                RollingHashKind::Adler32.create_with(params)
+            }),
+            (RollingHashKind::Fletcher32, |params| {
+                RollingHashKind::Fletcher32.create_with(params)
+            }),
+            (RollingHashKind::RabinKarp, |params| {
+                RollingHashKind::RabinKarp.create_with(params)
+            }),
+            (RollingHashKind::Buzhash, |params| {
+                RollingHashKind::Buzhash.create_with(params)
+            }),
+        ];
+        let factories = builtins
+            .into_iter()
+            .map(|(kind, factory)| (kind.name().to_string(), factory))
+            .collect();
+        RwLock::new(factories)
+    })
+}
+
+/// Make a rolling hash available under the given name. Names that are
+/// already taken, including the built in ones, can't be replaced.
+pub fn register(name: &str, factory: AlgorithmFactory) -> Result<(), DiffError> {
+    let mut factories = registry().write().unwrap_or_else(|err| err.into_inner());
+    if factories.contains_key(name) {
+        return Err(DiffError::AlgorithmExists(name.to_string()));
+    }
+    factories.insert(name.to_string(), factory);
+    Ok(())
+}
+
+/// Names of every registered rolling hash, sorted
+pub fn available() -> Vec<String> {
+    let factories = registry().read().unwrap_or_else(|err| err.into_inner());
+    factories.keys().cloned().collect()
+}
+
+/// Build the rolling hash from a spec, which is a registered name optionally
+/// followed by its params, like `rabin-karp:base=31,modulus=65521`
+pub fn create(spec: &str) -> Result<Box<dyn Algorithm>, DiffError> {
+    let (name, params) = parse_spec(spec)?;
+    let factories = registry().read().unwrap_or_else(|err| err.into_inner());
+    match factories.get(name) {
+        Some(factory) => factory(&params),
+        None => Err(DiffError::UnknownAlgorithm(name.to_string())),
+    }
+}
+
+/// Split a spec into the algorithm name and its params
+pub fn parse_spec(spec: &str) -> Result<(&str, AlgorithmParams), DiffError> {
+    let (name, rest) = match spec.split_once(':') {
+        Some((name, rest)) => (name, rest),
+        None => return Ok((spec, AlgorithmParams::new())),
+    };
+    let mut params = AlgorithmParams::new();
+    for param in rest.split(',') {
+        let (key, value) = param
+            .split_once('=')
+            .ok_or_else(|| DiffError::AlgorithmParams(format!("{} is not key=value", param)))?;
+        let value = value
+            .parse()
+            .map_err(|_| DiffError::AlgorithmParams(format!("{} is not a number", value)))?;
+        if params.insert(key.to_string(), value).is_some() {
+            return Err(DiffError::AlgorithmParams(format!("{} is set twice", key)));
+        }
+    }
+    Ok((name, params))
+}
+
+/// Spec that builds the same algorithm again, as recorded in the headers
+pub fn to_spec(name: &str, params: &AlgorithmParams) -> String {
+    if params.is_empty() {
+        return name.to_string();
+    }
+    let params: Vec<String> = params
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    format!("{}:{}", name, params.join(","))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_kind_names() {
+        for kind in RollingHashKind::ALL {
+            assert_eq!(kind.name().parse::<RollingHashKind>().unwrap(), kind);
+            assert!(available().contains(&kind.to_string()));
+        }
+        assert!(matches!(
+            "adlr".parse::<RollingHashKind>(),
+            Err(DiffError::UnknownAlgorithm(_))
+        ));
+    }
+
+    #[test]
+    fn test_create() {
+        let chunk = "hello world".as_bytes();
+        let hash = create("adler").unwrap().get_chunk_hash(chunk).unwrap();
+        assert_eq!(hash, Adler32::new().get_chunk_hash(chunk).unwrap());
+        let hash = create("fletcher").unwrap().get_chunk_hash(chunk).unwrap();
+        assert_eq!(hash, Fletcher32::new().get_chunk_hash(chunk).unwrap());
+        assert!(matches!(
+            create("fletch"),
+            Err(DiffError::UnknownAlgorithm(_))
+        ));
+    }
+
+    #[test]
+    fn test_specs() {
+        let (name, params) = parse_spec("rabin-karp:modulus=65521,base=31").unwrap();
+        assert_eq!(name, "rabin-karp");
+        assert_eq!(params["base"], 31);
+        assert_eq!(params["modulus"], 65521);
+        assert_eq!(to_spec(name, &params), "rabin-karp:base=31,modulus=65521");
+        assert_eq!(
+            parse_spec("adler").unwrap(),
+            ("adler", AlgorithmParams::new())
+        );
+        assert_eq!(to_spec("adler", &AlgorithmParams::new()), "adler");
+
+        for spec in [
+            "rabin-karp:base",
+            "rabin-karp:base=x",
+            "rabin-karp:base=3,base=5",
+        ] {
+            assert!(matches!(
+                parse_spec(spec),
+                Err(DiffError::AlgorithmParams(_))
+            ));
+        }
+        // Params are checked by the algorithm
+        assert!(create("rabin-karp:base=31").is_ok());
+        assert!(create("rabin-karp:seed=31").is_err());
+        assert!(create("adler:base=31").is_err());
+    }
+
+    #[test]
+    fn test_register() {
+        register("registry_test_adler", |_| Ok(Box::new(Adler32::new()))).unwrap();
+        assert!(available().contains(&"registry_test_adler".to_string()));
+        assert!(create("registry_test_adler").is_ok());
+        // Taken names are left alone
+        assert!(matches!(
+            register("registry_test_adler", |_| Ok(Box::new(Fletcher32::new()))),
+            Err(DiffError::AlgorithmExists(_))
+        ));
+        assert!(matches!(
+            register("adler", |_| Ok(Box::new(Fletcher32::new()))),
+            Err(DiffError::AlgorithmExists(_))
+        ));
+    }
+}
@@ -0,0 +1,198 @@
+use crate::algorithms::algorithm::AlgorithmParams;
+use crate::algorithms::registry::{parse_spec, to_spec};
+use crate::error::DiffError;
+use std::fmt;
+use std::str::FromStr;
+
+/// Hashes that can be used for the block checksums
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StrongHashKind {
+    Blake2s,
+    Blake2b,
+    Blake3,
+    Sha256,
+    Xxh3,
+}
+
+impl StrongHashKind {
+    pub const ALL: [StrongHashKind; 5] = [
+        StrongHashKind::Blake2s,
+        StrongHashKind::Blake2b,
+        StrongHashKind::Blake3,
+        StrongHashKind::Sha256,
+        StrongHashKind::Xxh3,
+    ];
+
+    /// Name the hash is selected by and recorded with in headers
+    pub fn name(&self) -> &'static str {
+        match self {
+            StrongHashKind::Blake2s => "blake2s256",
+            StrongHashKind::Blake2b => "blake2b512",
+            StrongHashKind::Blake3 => "blake3",
+            StrongHashKind::Sha256 => "sha256",
+            StrongHashKind::Xxh3 => "xxh3-128",
+        }
+    }
+
+    /// Length of the full digest in bytes
+    pub fn digest_len(&self) -> usize {
+        match self {
+            StrongHashKind::Blake2s => 32,
+            StrongHashKind::Blake2b => 64,
+            StrongHashKind::Blake3 => 32,
+            StrongHashKind::Sha256 => 32,
+            StrongHashKind::Xxh3 => 16,
+        }
+    }
+
+    /// Full digest of the data
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            StrongHashKind::Blake2s => {
+                use blake2::Digest;
+                blake2::Blake2s256::digest(data).to_vec()
+            }
+            StrongHashKind::Blake2b => {
+                use blake2::Digest;
+                blake2::Blake2b512::digest(data).to_vec()
+            }
+            StrongHashKind::Blake3 => blake3::hash(data).as_bytes().to_vec(),
+            StrongHashKind::Sha256 => {
+                use sha2::Digest;
+                sha2::Sha256::digest(data).to_vec()
+            }
+            StrongHashKind::Xxh3 => xxhash_rust::xxh3::xxh3_128(data).to_be_bytes().to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for StrongHashKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for StrongHashKind {
+    type Err = DiffError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        StrongHashKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or_else(|| DiffError::UnknownStrongHash(name.to_string()))
+    }
+}
+
+/// Strong hash of the blocks, optionally cut short. Shorter checksums make
+/// smaller signatures at the cost of a higher chance of a false match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StrongHash {
+    kind: StrongHashKind,
+    len: usize,
+}
+
+impl StrongHash {
+    pub fn new(kind: StrongHashKind, len: usize) -> Result<Self, DiffError> {
+        if len == 0 || len > kind.digest_len() {
+            return Err(DiffError::AlgorithmParams(format!(
+                "{} digests are 1 to {} bytes long, found {}",
+                kind,
+                kind.digest_len(),
+                len
+            )));
+        }
+        Ok(StrongHash { kind, len })
+    }
+
+    /// Parse a spec like `blake3` or `blake3:len=8`, the full digest is kept
+    /// unless a length is given
+    pub fn from_spec(spec: &str) -> Result<Self, DiffError> {
+        let (name, mut params) = parse_spec(spec)?;
+        let kind: StrongHashKind = name.parse()?;
+        let len = match params.remove("len") {
+            Some(len) => usize::try_from(len)
+                .map_err(|_| DiffError::AlgorithmParams(format!("{} is too long", len)))?,
+            None => kind.digest_len(),
+        };
+        if let Some(key) = params.keys().next() {
+            return Err(DiffError::AlgorithmParams(format!(
+                "{} doesn't take {}",
+                kind, key
+            )));
+        }
+        StrongHash::new(kind, len)
+    }
+
+    /// Spec that selects the same hash again, as recorded in the headers
+    pub fn to_spec(&self) -> String {
+        let mut params = AlgorithmParams::new();
+        if self.len != self.kind.digest_len() {
+            params.insert("len".to_string(), self.len as u64);
+        }
+        to_spec(self.kind.name(), &params)
+    }
+
+    pub fn kind(&self) -> StrongHashKind {
+        self.kind
+    }
+
+    /// Length of the checksums in bytes
+    pub fn checksum_len(&self) -> usize {
+        self.len
+    }
+
+    /// Checksum of a block, the digest cut down to the configured length
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        let mut digest = self.kind.digest(data);
+        digest.truncate(self.len);
+        digest
+    }
+}
+
+impl Default for StrongHash {
+    fn default() -> Self {
+        let kind = StrongHashKind::Blake2s;
+        StrongHash {
+            kind,
+            len: kind.digest_len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_digest_lengths() {
+        for kind in StrongHashKind::ALL {
+            assert_eq!(kind.digest(b"block").len(), kind.digest_len());
+            assert_eq!(kind.name().parse::<StrongHashKind>().unwrap(), kind);
+        }
+        assert!(matches!(
+            "md5".parse::<StrongHashKind>(),
+            Err(DiffError::UnknownStrongHash(_))
+        ));
+    }
+
+    #[test]
+    fn test_truncated_digest() {
+        let full = StrongHash::from_spec("sha256").unwrap();
+        let short = StrongHash::from_spec("sha256:len=8").unwrap();
+        assert_eq!(short.digest(b"block"), full.digest(b"block")[..8]);
+        assert_eq!(full.to_spec(), "sha256");
+        assert_eq!(short.to_spec(), "sha256:len=8");
+        assert_eq!(StrongHash::default().to_spec(), "blake2s256");
+    }
+
+    #[test]
+    fn test_bad_spec() {
+        assert!(StrongHash::from_spec("xxh3-128:len=0").is_err());
+        assert!(StrongHash::from_spec("xxh3-128:len=17").is_err());
+        assert!(StrongHash::from_spec("blake3:seed=1").is_err());
+        assert!(matches!(
+            StrongHash::from_spec("blake"),
+            Err(DiffError::UnknownStrongHash(_))
+        ));
+    }
+}
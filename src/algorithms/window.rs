@@ -0,0 +1,84 @@
+/// The bytes a rolling hash currently covers. Rolling a byte in overwrites
+/// the oldest one in place, so it costs the same whatever the window size.
+/// Every byte is kept twice, so the window can always be read as one slice.
+#[derive(Debug, Clone, Default)]
+pub struct RingWindow {
+    /// The window followed by a copy of it
+    bytes: Vec<u8>,
+    /// Position of the oldest byte
+    start: usize,
+}
+
+impl RingWindow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start over with the given bytes, reusing the buffer when it's big enough
+    pub fn fill(&mut self, chunk: &[u8]) {
+        self.bytes.clear();
+        self.bytes.extend_from_slice(chunk);
+        self.bytes.extend_from_slice(chunk);
+        self.start = 0;
+    }
+
+    /// Add a byte and hand back the oldest one, which just left the window.
+    /// An empty window hands back the byte it was given.
+    pub fn roll(&mut self, byte: u8) -> u8 {
+        let len = self.len();
+        if len == 0 {
+            return byte;
+        }
+        let out = std::mem::replace(&mut self.bytes[self.start], byte);
+        self.bytes[self.start + len] = byte;
+        self.start += 1;
+        if self.start == len {
+            self.start = 0;
+        }
+        out
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The bytes from oldest to newest
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[self.start..self.start + self.len()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roll() {
+        let mut window = RingWindow::new();
+        window.fill(b"abc");
+        assert_eq!(window.roll(b'd'), b'a');
+        assert_eq!(window.roll(b'e'), b'b');
+        assert_eq!(window.as_slice(), b"cde");
+        assert_eq!(window.roll(b'f'), b'c');
+        assert_eq!(window.roll(b'g'), b'd');
+        assert_eq!(window.as_slice(), b"efg");
+        assert_eq!(window.len(), 3);
+
+        // Filling again starts over
+        window.fill(b"xy");
+        assert_eq!(window.as_slice(), b"xy");
+        assert_eq!(window.roll(b'z'), b'x');
+    }
+
+    #[test]
+    fn test_roll_empty() {
+        let mut window = RingWindow::new();
+        assert_eq!(window.roll(b'a'), b'a');
+        assert!(window.is_empty());
+        assert_eq!(window.as_slice(), b"");
+    }
+}
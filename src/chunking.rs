@@ -0,0 +1,359 @@
+use crate::error::DiffError;
+use crate::utils::{read_block, splitmix64};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+const GEAR_SEED: u64 = 0x6765_6172_6364_6321;
+
+/// Largest block size that can be asked for
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+/// Smallest and largest block sizes picked by `auto`, the same as rsync
+pub const AUTO_MIN_CHUNK_SIZE: usize = 700;
+pub const AUTO_MAX_CHUNK_SIZE: usize = 128 * 1024;
+
+/// Block size as asked for on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChunkSize {
+    /// Picked from the length of the original file
+    #[default]
+    Auto,
+    Bytes(usize),
+}
+
+impl ChunkSize {
+    /// Block size to use for an original file of the given length. When the
+    /// length isn't known up front, `auto` falls back to the smallest size.
+    pub fn resolve(&self, basis_len: Option<u64>) -> Result<usize, DiffError> {
+        match self {
+            ChunkSize::Auto => Ok(basis_len.map_or(AUTO_MIN_CHUNK_SIZE, auto_chunk_size)),
+            ChunkSize::Bytes(size) => {
+                check_chunk_size(*size)?;
+                Ok(*size)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ChunkSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChunkSize::Auto => write!(f, "auto"),
+            ChunkSize::Bytes(size) => write!(f, "{}", size),
+        }
+    }
+}
+
+impl FromStr for ChunkSize {
+    type Err = DiffError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == "auto" {
+            return Ok(ChunkSize::Auto);
+        }
+        let size = value.parse().map_err(|_| {
+            DiffError::InvalidChunkSize(format!("{} is neither auto nor a number", value))
+        })?;
+        check_chunk_size(size)?;
+        Ok(ChunkSize::Bytes(size))
+    }
+}
+
+/// Block size for an original file of the given length. Like rsync it's
+/// the square root of the length rounded down to a multiple of 8, kept
+/// between 700 bytes and 128 KiB.
+pub fn auto_chunk_size(basis_len: u64) -> usize {
+    let size = (basis_len.isqrt() / 8 * 8) as usize;
+    size.clamp(AUTO_MIN_CHUNK_SIZE, AUTO_MAX_CHUNK_SIZE)
+}
+
+/// Block sizes have to be at least a byte and at most `MAX_CHUNK_SIZE`
+pub fn check_chunk_size(chunk_size: usize) -> Result<(), DiffError> {
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+        return Err(DiffError::InvalidChunkSize(format!(
+            "{} is not between 1 and {}",
+            chunk_size, MAX_CHUNK_SIZE
+        )));
+    }
+    Ok(())
+}
+
+/// How a file is cut into blocks
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Chunking {
+    /// Blocks of exactly `chunk_size` bytes, only the last one may be shorter
+    #[default]
+    Fixed,
+    /// Content defined blocks cut by a gear hash. They average `chunk_size`
+    /// bytes and are never shorter than `min` or longer than `max`, apart
+    /// from the last one.
+    Gear { min: usize, max: usize },
+}
+
+impl Chunking {
+    /// Gear chunking bounded by a quarter and four times the average size,
+    /// blocks are never longer than `MAX_CHUNK_SIZE`
+    pub fn gear(avg: usize) -> Self {
+        Chunking::Gear {
+            min: (avg / 4).max(1),
+            max: avg.saturating_mul(4).min(MAX_CHUNK_SIZE).max(avg),
+        }
+    }
+}
+
+/// Finds block boundaries with the FastCDC gear hash. Boundaries only
+/// depend on the bytes right before them, so an insertion only moves the
+/// boundaries around it.
+pub struct GearChunker {
+    min: usize,
+    avg: usize,
+    max: usize,
+    /// Harder to match, used before the average size is reached
+    mask_small: u64,
+    /// Easier to match, used after the average size is reached
+    mask_large: u64,
+}
+
+impl GearChunker {
+    /// The sizes usually come from a header, so they're checked before
+    /// a buffer of `max` bytes is set aside for them
+    pub fn new(min: usize, avg: usize, max: usize) -> Result<Self, DiffError> {
+        if min == 0 || min > avg || avg > max {
+            return Err(DiffError::Chunking(format!(
+                "expected 0 < min <= avg <= max, found {}, {} and {}",
+                min, avg, max
+            )));
+        }
+        if max > MAX_CHUNK_SIZE {
+            return Err(DiffError::Chunking(format!(
+                "blocks can be at most {} bytes, found a max of {}",
+                MAX_CHUNK_SIZE, max
+            )));
+        }
+        let bits = avg.ilog2();
+        Ok(GearChunker {
+            min,
+            avg,
+            max,
+            mask_small: high_mask(bits + 1),
+            mask_large: high_mask(bits.saturating_sub(1)),
+        })
+    }
+
+    /// Chunker for the given chunking, `None` for fixed blocks
+    pub fn from_chunking(chunking: Chunking, chunk_size: usize) -> Result<Option<Self>, DiffError> {
+        match chunking {
+            Chunking::Fixed => Ok(None),
+            Chunking::Gear { min, max } => Ok(Some(GearChunker::new(min, chunk_size, max)?)),
+        }
+    }
+
+    /// Length of the first block of the data. The data has to hold `max`
+    /// bytes unless it's the end of the input.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min {
+            return data.len();
+        }
+        let end = data.len().min(self.max);
+        let normal = end.min(self.avg);
+        let gear = gear_table();
+
+        let mut hash: u64 = 0;
+        for (index, byte) in data.iter().enumerate().take(end).skip(self.min) {
+            hash = (hash << 1).wrapping_add(gear[*byte as usize]);
+            let mask = match index < normal {
+                true => self.mask_small,
+                false => self.mask_large,
+            };
+            if hash & mask == 0 {
+                return index + 1;
+            }
+        }
+        end
+    }
+
+    /// Cut the reader into blocks, every block is handed to `f` along with
+    /// its offset. Only `max` bytes of the input are held at a time.
+    pub fn for_each_block<R: Read>(
+        &self,
+        mut reader: R,
+        mut f: impl FnMut(u64, &[u8]) -> Result<(), DiffError>,
+    ) -> Result<(), DiffError> {
+        let mut buffer = vec![0; self.max];
+        let mut filled = 0;
+        let mut offset = 0;
+        loop {
+            filled += read_block(&mut reader, &mut buffer[filled..])?;
+            if filled == 0 {
+                return Ok(());
+            }
+            let len = self.cut(&buffer[..filled]);
+            f(offset, &buffer[..len])?;
+            buffer.copy_within(len..filled, 0);
+            filled -= len;
+            offset += len as u64;
+        }
+    }
+}
+
+/// Cut the reader into blocks of `chunk_size` bytes, the last one holds
+/// whatever is left. Every block is handed to `f` along with its offset.
+pub fn for_each_fixed_block<R: Read>(
+    mut reader: R,
+    chunk_size: usize,
+    mut f: impl FnMut(u64, &[u8]) -> Result<(), DiffError>,
+) -> Result<(), DiffError> {
+    let mut buffer = vec![0; chunk_size];
+    let mut offset = 0;
+    loop {
+        let filled = read_block(&mut reader, &mut buffer)?;
+        if filled == 0 {
+            return Ok(());
+        }
+        f(offset, &buffer[..filled])?;
+        if filled < chunk_size {
+            return Ok(());
+        }
+        offset += filled as u64;
+    }
+}
+
+/// Mask of the top `bits` bits, the low bits of a gear hash only depend
+/// on the last few bytes
+fn high_mask(bits: u32) -> u64 {
+    match bits {
+        0 => 0,
+        _ => u64::MAX << (64 - bits.min(64)),
+    }
+}
+
+fn gear_table() -> &'static [u64; 256] {
+    static GEAR: OnceLock<[u64; 256]> = OnceLock::new();
+    GEAR.get_or_init(|| {
+        let mut state = GEAR_SEED;
+        let mut table = [0; 256];
+        for entry in table.iter_mut() {
+            *entry = splitmix64(&mut state);
+        }
+        table
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cut_bounds() {
+        let chunker = GearChunker::new(16, 64, 256).unwrap();
+        let data = get_data(4096);
+        let mut offset = 0;
+        while offset < data.len() {
+            let len = chunker.cut(&data[offset..]);
+            if offset + len < data.len() {
+                assert!((16..=256).contains(&len));
+            }
+            offset += len;
+        }
+        // Short input is a single block
+        assert_eq!(chunker.cut(&data[..10]), 10);
+        assert_eq!(chunker.cut(&[]), 0);
+    }
+
+    #[test]
+    fn test_for_each_block() {
+        let chunker = GearChunker::new(8, 32, 128).unwrap();
+        let data = get_data(5000);
+        let mut joined = Vec::new();
+        chunker
+            .for_each_block(data.as_slice(), |offset, block| {
+                assert_eq!(offset, joined.len() as u64);
+                // Same boundaries as cutting the whole buffer
+                assert_eq!(block.len(), chunker.cut(&data[joined.len()..]));
+                joined.extend_from_slice(block);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(joined, data);
+    }
+
+    #[test]
+    fn test_insert_keeps_boundaries() {
+        let chunker = GearChunker::new(16, 64, 256).unwrap();
+        let data = get_data(8192);
+        let mut modified = b"inserted".to_vec();
+        modified.extend_from_slice(&data);
+
+        let boundaries = |data: &[u8]| {
+            let mut ends = Vec::new();
+            chunker
+                .for_each_block(data, |offset, block| {
+                    ends.push(offset + block.len() as u64);
+                    Ok(())
+                })
+                .unwrap();
+            ends
+        };
+        let original = boundaries(&data);
+        let shifted: Vec<u64> = boundaries(&modified).iter().map(|end| end - 8).collect();
+        // Boundaries line up again a few blocks after the insertion
+        let shared = original.iter().filter(|end| shifted.contains(end)).count();
+        assert!(shared * 10 >= original.len() * 9);
+    }
+
+    #[test]
+    fn test_auto_chunk_size() {
+        assert_eq!(auto_chunk_size(0), AUTO_MIN_CHUNK_SIZE);
+        assert_eq!(auto_chunk_size(1000), AUTO_MIN_CHUNK_SIZE);
+        // sqrt(1e8) is 10000, already a multiple of 8
+        assert_eq!(auto_chunk_size(100_000_000), 10000);
+        assert_eq!(auto_chunk_size(100_000_100), 10000);
+        assert_eq!(auto_chunk_size(10_001 * 10_001), 10000);
+        assert_eq!(auto_chunk_size(u64::MAX), AUTO_MAX_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn test_chunk_size_from_str() {
+        assert_eq!("auto".parse::<ChunkSize>().unwrap(), ChunkSize::Auto);
+        assert_eq!(
+            "1048576".parse::<ChunkSize>().unwrap(),
+            ChunkSize::Bytes(1024 * 1024)
+        );
+        for value in ["0", "-4", "big", "4.5", "33554432"] {
+            assert!(matches!(
+                value.parse::<ChunkSize>(),
+                Err(DiffError::InvalidChunkSize(_))
+            ));
+        }
+        assert_eq!(ChunkSize::Auto.resolve(None).unwrap(), AUTO_MIN_CHUNK_SIZE);
+        assert_eq!(ChunkSize::Bytes(300).resolve(Some(10)).unwrap(), 300);
+        assert!(ChunkSize::Bytes(0).resolve(None).is_err());
+    }
+
+    #[test]
+    fn test_new_checks_sizes() {
+        assert!(GearChunker::new(0, 8, 16).is_err());
+        assert!(GearChunker::new(16, 8, 32).is_err());
+        assert!(GearChunker::new(4, 32, 16).is_err());
+        assert!(GearChunker::new(1, 1, 1).is_ok());
+        assert!(GearChunker::new(1, 8, MAX_CHUNK_SIZE).is_ok());
+        assert!(GearChunker::new(1, 8, MAX_CHUNK_SIZE + 1).is_err());
+
+        // The largest blocks can still be cut by content
+        assert_eq!(
+            Chunking::gear(MAX_CHUNK_SIZE),
+            Chunking::Gear {
+                min: MAX_CHUNK_SIZE / 4,
+                max: MAX_CHUNK_SIZE,
+            }
+        );
+        assert!(GearChunker::from_chunking(Chunking::gear(MAX_CHUNK_SIZE), MAX_CHUNK_SIZE).is_ok());
+    }
+
+    fn get_data(len: usize) -> Vec<u8> {
+        let mut state = 1;
+        (0..len).map(|_| splitmix64(&mut state) as u8).collect()
+    }
+}
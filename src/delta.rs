@@ -1,151 +1,381 @@
-use crate::algorithms::adler_32::Adler32;
 use crate::algorithms::algorithm::Algorithm;
-use crate::algorithms::fletcher_32::Fletcher32;
-use crate::error::DiffError;
-use crate::sign::Signature;
-use crate::utils::get_blake2;
-use log::error;
+use crate::algorithms::registry;
+use crate::algorithms::strong_hash::StrongHash;
+use crate::chunking::{check_chunk_size, GearChunker};
+use crate::error::{create_file, open_file, DiffError};
+use crate::format::{delta_sink, DeltaWriter, Format};
+use crate::header::Header;
+use crate::sign::{SignatureIndex, SignatureSet};
+use crate::utils::Blake2Reader;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::collections::HashMap;
-use std::fmt;
-use std::fs::File;
-use std::io::Read;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum Delta {
-    I(u32), // Index
-    B(u8),  // Byte
+    /// Bytes that could not be found in the original file
+    Literal(Vec<u8>),
+    /// A run of neighbouring blocks from the original file
+    Copy { start_block: u64, block_count: u64 },
+    /// An arbitrary byte range from the original file
+    CopyRange { offset: u64, len: u64 },
 }
 
-pub struct HashBlock {
-    index: u32,
-    weak_hash: u32,
-    bytes: Vec<u8>,
+/// Delta instructions along with the header of the signatures they were made from
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct DeltaSet {
+    pub header: Header,
+    pub delta: Vec<Delta>,
+    /// Blake2 digest of the modified (target) file, checked after patching
+    pub target_digest: Vec<u8>,
 }
 
-impl fmt::Debug for HashBlock {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "HashBlock index: {}, hash: {} bytes: {:?}",
-            self.index,
-            self.weak_hash,
-            std::str::from_utf8(&self.bytes)
-        )
+/// Longest literal run that is held in memory before it's written out
+pub const MAX_LITERAL_LEN: usize = 64 * 1024;
+
+/// Where a delta goes as it's worked out. Instructions come in order, then
+/// the digest of the modified data once they are all out. Signing, diffing
+/// and encoding stay separate, so any of them can be swapped out.
+pub trait DeltaSink {
+    fn push_op(&mut self, op: Delta) -> Result<(), DiffError>;
+
+    /// Called once, after the last instruction
+    fn push_digest(&mut self, target_digest: Vec<u8>) -> Result<(), DiffError>;
+}
+
+/// Collects the delta in memory
+impl DeltaSink for DeltaSet {
+    fn push_op(&mut self, op: Delta) -> Result<(), DiffError> {
+        self.delta.push(op);
+        Ok(())
+    }
+
+    fn push_digest(&mut self, target_digest: Vec<u8>) -> Result<(), DiffError> {
+        self.target_digest = target_digest;
+        Ok(())
+    }
+}
+
+impl DeltaSet {
+    /// Delta with no instructions yet, ready to be used as a sink
+    pub fn new(header: Header) -> Self {
+        DeltaSet {
+            header,
+            delta: Vec::new(),
+            target_digest: Vec::new(),
+        }
     }
 }
 
+/// Generate the delta of the file against the signatures of the original file.
+/// The chunk size and hashes are taken from the signature header.
 pub fn gen_delta_from_file(
     path: &str,
-    chunk_size: usize,
-    algorithm: &str,
     output_path: &str,
-    signatures: HashMap<u32, Signature>,
-) -> Result<Vec<Delta>, DiffError> {
-    let mut f = File::open(path)?;
-    let mut buffer = Vec::<u8>::new();
-    f.read_to_end(&mut buffer)?; //TODO
+    format: Format,
+    signature_set: SignatureSet,
+) -> Result<(), DiffError> {
+    let f = open_file(path)?;
+    let mut writer = BufWriter::new(create_file(output_path)?);
+    {
+        let mut sink = delta_sink(&mut writer, &signature_set.header, format)?;
+        gen_delta_into(f, &signature_set, &mut *sink)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Generate the delta of data that's already in memory against the
+/// signatures of the original
+pub fn gen_delta_from_bytes(
+    data: &[u8],
+    signature_set: &SignatureSet,
+) -> Result<DeltaSet, DiffError> {
+    let mut delta_set = DeltaSet::new(signature_set.header.clone());
+    gen_delta_into(data, signature_set, &mut delta_set)?;
+    Ok(delta_set)
+}
+
+/// Generate the delta of the reader against the signatures, the binary delta
+/// is written out instruction by instruction as the reader is consumed
+pub fn gen_delta<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    signature_set: &SignatureSet,
+) -> Result<(), DiffError> {
+    let mut delta_writer = DeltaWriter::new(writer, &signature_set.header)?;
+    gen_delta_into(reader, signature_set, &mut delta_writer)
+}
 
-    let mut delta = Vec::<Delta>::new();
+/// Generate the delta of the reader against the signatures and hand it to
+/// the sink as the reader is consumed
+pub fn gen_delta_into<R: Read, S: DeltaSink + ?Sized>(
+    reader: R,
+    signature_set: &SignatureSet,
+    sink: &mut S,
+) -> Result<(), DiffError> {
+    let target_digest = stream_delta(reader, signature_set, |op| sink.push_op(op))?;
+    sink.push_digest(target_digest)
+}
 
-    // TODO what happens if chunk is smaller than chunk size
+/// Roll the weak hash over the reader and hand every finished instruction to
+/// `emit`. Returns the Blake2 digest of everything that was read.
+fn stream_delta<R: Read>(
+    reader: R,
+    signature_set: &SignatureSet,
+    mut emit: impl FnMut(Delta) -> Result<(), DiffError>,
+) -> Result<Vec<u8>, DiffError> {
+    let header = &signature_set.header;
+    check_chunk_size(header.chunk_size)?;
+
+    let mut algo = registry::create(&header.algorithm)?;
+    let strong_hash = StrongHash::from_spec(&header.strong_hash)?;
+    let mut reader = BufReader::new(Blake2Reader::new(reader));
+    let algo = &mut *algo;
+    match GearChunker::from_chunking(header.chunking, header.chunk_size)? {
+        Some(chunker) => match_blocks(
+            &mut reader,
+            &chunker,
+            algo,
+            &strong_hash,
+            signature_set,
+            &mut emit,
+        )?,
+        None => roll(&mut reader, algo, &strong_hash, signature_set, &mut emit)?,
+    }
 
-    let hashes = match algorithm {
-        "fletcher" => {
-            let algo = Fletcher32::new();
-            calculate_rolling_hashes(chunk_size, algo, &buffer)?
+    let (target_digest, _) = reader.into_inner().finalize();
+    Ok(target_digest)
+}
+
+/// Slide a window of one block over the reader. Only the window and the
+/// instruction being built are kept in memory.
+fn roll<R: BufRead>(
+    reader: &mut R,
+    algo: &mut dyn Algorithm,
+    strong_hash: &StrongHash,
+    signature_set: &SignatureSet,
+    emit: &mut impl FnMut(Delta) -> Result<(), DiffError>,
+) -> Result<(), DiffError> {
+    let SignatureSet { header, signatures } = signature_set;
+    let chunk_size = header.chunk_size;
+    let tail = header.tail_block();
+    let mut pending = Vec::new();
+    let mut window = VecDeque::with_capacity(chunk_size);
+    let mut bytes = reader.bytes();
+
+    loop {
+        fill_window(&mut bytes, &mut window, chunk_size)?;
+        if window.len() < chunk_size {
+            // Not enough left for a whole block
+            let rest = window.make_contiguous();
+            push_rest(&mut pending, rest, signatures, tail, algo, strong_hash)?;
+            break;
         }
 
-        _ => {
-            let algo = Adler32::new();
-            calculate_rolling_hashes(chunk_size, algo, &buffer)?
+        let mut weak_hash = algo.get_chunk_hash(window.make_contiguous())?;
+        loop {
+            if let Some(index) = find_block(signatures, strong_hash, weak_hash, &mut window) {
+                push_block(&mut pending, index);
+                window.clear();
+                break;
+            }
+            // If we are here, key does not match, it's modified data
+            match bytes.next().transpose()? {
+                Some(byte) => {
+                    if let Some(out) = window.pop_front() {
+                        push_literal(&mut pending, &[out]);
+                    }
+                    window.push_back(byte);
+                    weak_hash = algo.get_rolling_hash(&byte)?;
+                }
+                None => {
+                    let rest = window.make_contiguous();
+                    push_rest(&mut pending, rest, signatures, tail, algo, strong_hash)?;
+                    window.clear();
+                    break;
+                }
+            }
+            flush_pending(&mut pending, emit)?;
         }
-    };
-
-    let mut index = 0;
-    while index < buffer.len() {        
-        if index > buffer.len() - chunk_size {
-            let chunk = &buffer[index..];
-            // Last iterable index
-            for byte in chunk {
-                delta.push(Delta::B(*byte))
+        flush_pending(&mut pending, emit)?;
+    }
+
+    for op in pending {
+        emit(op)?;
+    }
+    Ok(())
+}
+
+/// Cut the reader with the same chunker as the original file and look up
+/// every block. Content defined blocks line up again after an edit, so
+/// there is no need to roll the hash byte by byte.
+fn match_blocks<R: Read>(
+    reader: &mut R,
+    chunker: &GearChunker,
+    algo: &mut dyn Algorithm,
+    strong_hash: &StrongHash,
+    signature_set: &SignatureSet,
+    emit: &mut impl FnMut(Delta) -> Result<(), DiffError>,
+) -> Result<(), DiffError> {
+    let mut pending = Vec::new();
+    chunker.for_each_block(reader, |_, block| {
+        let weak_hash = algo.get_chunk_hash(block)?;
+        let sign = match signature_set.signatures.get(&weak_hash) {
+            Some(signs) => {
+                let checksum = strong_hash.digest(block);
+                signs
+                    .iter()
+                    .find(|sign| sign.len == block.len() as u64 && sign.checksum == checksum)
             }
-            break;
+            None => None,
+        };
+        match sign {
+            Some(sign) => push_range(&mut pending, sign.offset, sign.len),
+            None => push_literal(&mut pending, block),
         }
+        flush_pending(&mut pending, emit)
+    })?;
 
-        let curr_hash = &hashes[index].weak_hash;
-        let curr_bytes = hashes[index].bytes.clone();
-        if signatures.contains_key(&curr_hash) {
-            // Key match!
-            if let Some(sign) = &signatures.get(&curr_hash) {
-                let checksum = &sign.checksum;
-                let this_checksum = get_blake2(curr_bytes)?;
-                if checksum == &this_checksum {
-                    delta.push(Delta::I(sign.index));
-                    index = index + chunk_size;
-                    continue;
+    for op in pending {
+        emit(op)?;
+    }
+    Ok(())
+}
+
+/// Add the bytes left at the end of the input. They may end with the short
+/// last block of the original file, which is only looked for here.
+fn push_rest(
+    delta: &mut Vec<Delta>,
+    rest: &[u8],
+    signatures: &SignatureIndex,
+    tail: Option<(u64, usize)>,
+    algo: &mut dyn Algorithm,
+    strong_hash: &StrongHash,
+) -> Result<(), DiffError> {
+    if let Some((index, len)) = tail {
+        if rest.len() >= len {
+            let (head, block) = rest.split_at(rest.len() - len);
+            let weak_hash = algo.get_chunk_hash(block)?;
+            if let Some(signs) = signatures.get(&weak_hash) {
+                let checksum = strong_hash.digest(block);
+                if signs
+                    .iter()
+                    .any(|sign| sign.index == index && sign.checksum == checksum)
+                {
+                    push_literal(delta, head);
+                    push_block(delta, index);
+                    return Ok(());
                 }
-            } else {
-                error!("Something went wrong!, This is not supposed to happen.");
-                panic!();
             }
         }
-        // If we are here, key does not match, it's modified data
-        delta.push(Delta::B(buffer[index]));
-        index = index + 1;
     }
+    push_literal(delta, rest);
+    Ok(())
+}
 
-    // Write to the output file
-    let f = File::create(output_path)?;
-    serde_json::to_writer(&f, &delta)?;
+/// Read bytes into the window until it holds a block or the input runs out
+fn fill_window<I: Iterator<Item = std::io::Result<u8>>>(
+    bytes: &mut I,
+    window: &mut VecDeque<u8>,
+    chunk_size: usize,
+) -> Result<(), DiffError> {
+    while window.len() < chunk_size {
+        match bytes.next().transpose()? {
+            Some(byte) => window.push_back(byte),
+            None => break,
+        }
+    }
+    Ok(())
+}
 
-    Ok(delta)
+/// Find the block with the same length, weak and strong hash as the
+/// window. Blocks that only share the weak hash are skipped.
+fn find_block(
+    signatures: &SignatureIndex,
+    strong_hash: &StrongHash,
+    weak_hash: u32,
+    window: &mut VecDeque<u8>,
+) -> Option<u64> {
+    let signs = signatures.get(&weak_hash)?;
+    let checksum = strong_hash.digest(window.make_contiguous());
+    let sign = signs
+        .iter()
+        .find(|sign| sign.len == window.len() as u64 && sign.checksum == checksum)?;
+    Some(sign.index)
 }
 
-fn calculate_rolling_hashes(
-    chunk_size: usize,
-    mut algo: impl Algorithm,
-    buffer: &Vec<u8>,
-) -> Result<Vec<HashBlock>, DiffError> {
-    let chunk = &buffer[0..chunk_size];
-    let mut index = 0;
-    let weak_hash = algo.get_chunk_hash(chunk)?;
-    let first_hash_block = HashBlock {
-        index,
-        weak_hash,
-        bytes: chunk.to_vec(),
-    };
-
-    let mut hash_block_list = Vec::new();
-    hash_block_list.push(first_hash_block);
-
-    for byte in &buffer[chunk_size..] {
-        index = index + 1;
-        let weak_hash = algo.get_rolling_hash(byte)?;
-        let chunk = algo.get_current_window()?;
-        let new_hash_block = HashBlock {
-            index,
-            weak_hash,
-            bytes: chunk.to_vec(),
-        };
+/// Hand out every instruction but the last one, which may still grow
+fn flush_pending(
+    pending: &mut Vec<Delta>,
+    emit: &mut impl FnMut(Delta) -> Result<(), DiffError>,
+) -> Result<(), DiffError> {
+    while pending.len() > 1 {
+        emit(pending.remove(0))?;
+    }
+    Ok(())
+}
+
+/// Add a matched block, growing the previous copy if the blocks are neighbours
+fn push_block(delta: &mut Vec<Delta>, index: u64) {
+    if let Some(Delta::Copy {
+        start_block,
+        block_count,
+    }) = delta.last_mut()
+    {
+        if *start_block + *block_count == index {
+            *block_count += 1;
+            return;
+        }
+    }
+    delta.push(Delta::Copy {
+        start_block: index,
+        block_count: 1,
+    });
+}
 
-        hash_block_list.push(new_hash_block);
+/// Add a matched byte range, growing the previous range if they touch
+fn push_range(delta: &mut Vec<Delta>, offset: u64, len: u64) {
+    if let Some(Delta::CopyRange {
+        offset: last_offset,
+        len: last_len,
+    }) = delta.last_mut()
+    {
+        if *last_offset + *last_len == offset {
+            if let Some(joined) = last_len.checked_add(len) {
+                *last_len = joined;
+                return;
+            }
+        }
     }
+    delta.push(Delta::CopyRange { offset, len });
+}
 
-    Ok(hash_block_list)
+/// Add modified bytes, growing the previous literal run until it's full
+fn push_literal(delta: &mut Vec<Delta>, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    if let Some(Delta::Literal(run)) = delta.last_mut() {
+        if run.len() + bytes.len() <= MAX_LITERAL_LEN {
+            run.extend_from_slice(bytes);
+            return;
+        }
+    }
+    delta.push(Delta::Literal(bytes.to_vec()));
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::chunking::Chunking;
     use crate::delta::Delta;
-    use crate::sign::Signature;
+    use crate::format::{load_delta, read_delta, read_signatures};
+    use crate::sign::{SignMode, Signature};
+    use crate::utils::{get_blake2, splitmix64};
     use rand::{thread_rng, Rng};
-    use std::{
-        fs::{remove_file, write, File},
-        io::BufReader,
-    };
+    use std::fs::{remove_file, write, File};
 
     const TEST_IN_FILE: &str = "data/tmp/delta_test_input.txt";
     const TEST_M_IN_FILE: &str = "data/tmp/delta_test_m_input.txt";
@@ -163,6 +393,410 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_push_block() {
+        let mut delta = Vec::new();
+        push_block(&mut delta, 3);
+        push_block(&mut delta, 4);
+        push_block(&mut delta, 5);
+        // Not a neighbour, starts a new copy
+        push_block(&mut delta, 1);
+        let expected = vec![
+            Delta::Copy {
+                start_block: 3,
+                block_count: 3,
+            },
+            Delta::Copy {
+                start_block: 1,
+                block_count: 1,
+            },
+        ];
+        assert_eq!(delta, expected);
+    }
+
+    #[test]
+    fn test_push_literal() {
+        let mut delta = Vec::new();
+        push_literal(&mut delta, b"he");
+        push_literal(&mut delta, b"llo");
+        push_block(&mut delta, 0);
+        push_literal(&mut delta, b"!");
+        let expected = vec![
+            Delta::Literal(b"hello".to_vec()),
+            Delta::Copy {
+                start_block: 0,
+                block_count: 1,
+            },
+            Delta::Literal(b"!".to_vec()),
+        ];
+        assert_eq!(delta, expected);
+    }
+
+    #[test]
+    fn test_gen_delta_from_file_runs() {
+        let chunk_size = 4;
+        let algorithm = "adler";
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        let tmp_m_in_file = format!("{}_{}", TEST_M_IN_FILE, get_rnum());
+        let tmp_out_file = format!("{}_{}", TEST_DELTA_FILE, get_rnum());
+
+        // 32 bytes, 8 blocks
+        let data = "The quick brown fox jumps over t";
+        write(&tmp_in_file, data).unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Lean).unwrap();
+
+        // Unchanged file is a single copy
+        write(&tmp_m_in_file, data).unwrap();
+        gen_delta_from_file(
+            &tmp_m_in_file,
+            &tmp_out_file,
+            Format::Json,
+            signatures.clone(),
+        )
+        .unwrap();
+        let delta = load_delta(&tmp_out_file).unwrap();
+        let expected = vec![Delta::Copy {
+            start_block: 0,
+            block_count: 8,
+        }];
+        assert_eq!(delta.delta, expected);
+
+        // An inserted sentence is a single literal run
+        let modified_data = "The quick brown A lazy dog sleeps. fox jumps over t";
+        write(&tmp_m_in_file, modified_data).unwrap();
+        gen_delta_from_file(&tmp_m_in_file, &tmp_out_file, Format::Binary, signatures).unwrap();
+        let delta = load_delta(&tmp_out_file).unwrap();
+        let expected = vec![
+            Delta::Copy {
+                start_block: 0,
+                block_count: 4,
+            },
+            Delta::Literal(b"A lazy dog sleeps. ".to_vec()),
+            Delta::Copy {
+                start_block: 4,
+                block_count: 4,
+            },
+        ];
+        assert_eq!(delta.delta, expected);
+
+        // Cleanup
+        remove_file(tmp_in_file).unwrap();
+        remove_file(tmp_m_in_file).unwrap();
+        remove_file(tmp_out_file).unwrap();
+    }
+
+    #[test]
+    fn test_gen_delta_bad_chunk_size() {
+        let (mut signatures, _) =
+            Signature::gen_sigs_from_reader(&b"0123456789"[..], 4, "adler", SignMode::Lean)
+                .unwrap();
+        signatures.header.chunk_size = 0;
+        let result = gen_delta(&b"0123"[..], Vec::new(), &signatures);
+        assert!(matches!(result, Err(DiffError::InvalidChunkSize(_))));
+    }
+
+    #[test]
+    fn test_gen_delta_oversized_gear() {
+        // A signature file asking for blocks far larger than allowed
+        let (signatures, _) = Signature::gen_sigs_chunked(
+            &b"0123456789"[..],
+            4,
+            Chunking::gear(4),
+            "adler",
+            "blake2s256",
+            SignMode::Lean,
+        )
+        .unwrap();
+        let json = serde_json::to_string(&signatures)
+            .unwrap()
+            .replace("\"max\":16", "\"max\":1000000000000000");
+        let result = read_signatures(json.as_bytes());
+        assert!(matches!(result, Err(DiffError::Format(_))));
+
+        // Signatures built in memory are checked before they're used
+        let mut signatures = signatures;
+        signatures.header.chunking = Chunking::Gear {
+            min: 1,
+            max: 1_000_000_000_000_000,
+        };
+        let result = gen_delta_from_bytes(b"0123", &signatures);
+        assert!(matches!(result, Err(DiffError::Chunking(_))));
+    }
+
+    #[test]
+    fn test_gen_delta_stream() {
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        write(&tmp_in_file, "0123456789abcdef").unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, 4, "fletcher", SignMode::Lean).unwrap();
+
+        // Shorter than a block, all of it is a literal
+        let mut output = Vec::new();
+        gen_delta(&b"01"[..], &mut output, &signatures).unwrap();
+        let delta = read_delta(output.as_slice()).unwrap();
+        assert_eq!(delta.header, signatures.header);
+        assert_eq!(delta.delta, vec![Delta::Literal(b"01".to_vec())]);
+        assert_eq!(delta.target_digest, get_blake2(b"01".to_vec()).unwrap());
+
+        // Empty input has no instructions at all
+        let mut output = Vec::new();
+        gen_delta(&b""[..], &mut output, &signatures).unwrap();
+        let delta = read_delta(output.as_slice()).unwrap();
+        assert!(delta.delta.is_empty());
+
+        let mut output = Vec::new();
+        gen_delta(&b"x4567cdef012"[..], &mut output, &signatures).unwrap();
+        let delta = read_delta(output.as_slice()).unwrap();
+        let expected = vec![
+            Delta::Literal(b"x".to_vec()),
+            Delta::Copy {
+                start_block: 1,
+                block_count: 1,
+            },
+            Delta::Copy {
+                start_block: 3,
+                block_count: 1,
+            },
+            Delta::Literal(b"012".to_vec()),
+        ];
+        assert_eq!(delta.delta, expected);
+        remove_file(tmp_in_file).unwrap();
+    }
+
+    #[test]
+    fn test_gen_delta_from_bytes() {
+        let (signatures, _) =
+            Signature::gen_sigs_from_bytes(b"0123456789abcdef", 4, "adler", SignMode::Lean)
+                .unwrap();
+        let modified = b"89ab-0123";
+        let delta = gen_delta_from_bytes(modified, &signatures).unwrap();
+
+        // Same as the streamed delta
+        let mut output = Vec::new();
+        gen_delta(&modified[..], &mut output, &signatures).unwrap();
+        assert_eq!(delta, read_delta(output.as_slice()).unwrap());
+        assert_eq!(
+            delta.delta[..2],
+            [
+                Delta::Copy {
+                    start_block: 2,
+                    block_count: 1,
+                },
+                Delta::Literal(b"-".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gen_delta_checks_len() {
+        // Blocks are only matched when their length agrees as well
+        let data = b"0123456789abcdef0123456789abcdef0123456789abcdef";
+        for chunking in [Chunking::Fixed, Chunking::gear(8)] {
+            let (mut signatures, _) = Signature::gen_sigs_chunked(
+                &data[..],
+                8,
+                chunking,
+                "adler",
+                "blake3",
+                SignMode::Lean,
+            )
+            .unwrap();
+            let delta = gen_delta_from_bytes(data, &signatures).unwrap();
+            assert!(delta
+                .delta
+                .iter()
+                .all(|op| !matches!(op, Delta::Literal(_))));
+
+            for sign in signatures.signatures.values_mut().flatten() {
+                sign.len += 1;
+            }
+            let delta = gen_delta_from_bytes(data, &signatures).unwrap();
+            assert_eq!(delta.delta, [Delta::Literal(data.to_vec())]);
+        }
+    }
+
+    #[test]
+    fn test_gen_delta_into() {
+        // A sink that only keeps count, nothing is encoded or stored
+        #[derive(Default)]
+        struct Tally {
+            literal_bytes: usize,
+            copies: usize,
+            digest: Option<Vec<u8>>,
+        }
+        impl DeltaSink for Tally {
+            fn push_op(&mut self, op: Delta) -> Result<(), DiffError> {
+                assert!(self.digest.is_none());
+                match op {
+                    Delta::Literal(bytes) => self.literal_bytes += bytes.len(),
+                    _ => self.copies += 1,
+                }
+                Ok(())
+            }
+
+            fn push_digest(&mut self, target_digest: Vec<u8>) -> Result<(), DiffError> {
+                assert!(self.digest.replace(target_digest).is_none());
+                Ok(())
+            }
+        }
+
+        let (signatures, _) =
+            Signature::gen_sigs_from_bytes(b"0123456789abcdef", 4, "adler", SignMode::Lean)
+                .unwrap();
+        let modified = b"4567--cdef0123";
+        let mut tally = Tally::default();
+        gen_delta_into(&modified[..], &signatures, &mut tally).unwrap();
+        assert_eq!(tally.literal_bytes, 2);
+        assert_eq!(tally.copies, 3);
+        assert_eq!(tally.digest, Some(get_blake2(modified.to_vec()).unwrap()));
+    }
+
+    #[test]
+    fn test_gen_delta_tail() {
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        write(&tmp_in_file, "0123456789").unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, 4, "adler", SignMode::Lean).unwrap();
+
+        // The short last block is matched at the end of the file
+        let cases = [
+            (
+                "0123456789",
+                vec![Delta::Copy {
+                    start_block: 0,
+                    block_count: 3,
+                }],
+            ),
+            (
+                "x0123456789",
+                vec![
+                    Delta::Literal(b"x".to_vec()),
+                    Delta::Copy {
+                        start_block: 0,
+                        block_count: 3,
+                    },
+                ],
+            ),
+            (
+                "4567xy89",
+                vec![
+                    Delta::Copy {
+                        start_block: 1,
+                        block_count: 1,
+                    },
+                    Delta::Literal(b"xy".to_vec()),
+                    Delta::Copy {
+                        start_block: 2,
+                        block_count: 1,
+                    },
+                ],
+            ),
+            // Anywhere else it's sent as literal bytes
+            (
+                "0123456789!",
+                vec![
+                    Delta::Copy {
+                        start_block: 0,
+                        block_count: 2,
+                    },
+                    Delta::Literal(b"89!".to_vec()),
+                ],
+            ),
+        ];
+        for (data, expected) in cases {
+            let mut output = Vec::new();
+            gen_delta(data.as_bytes(), &mut output, &signatures).unwrap();
+            let delta = read_delta(output.as_slice()).unwrap();
+            assert_eq!(delta.delta, expected, "{}", data);
+        }
+
+        // Files shorter than a block match as a whole
+        write(&tmp_in_file, "01").unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, 4, "fletcher", SignMode::Lean).unwrap();
+        let mut output = Vec::new();
+        gen_delta(&b"01"[..], &mut output, &signatures).unwrap();
+        let delta = read_delta(output.as_slice()).unwrap();
+        let expected = vec![Delta::Copy {
+            start_block: 0,
+            block_count: 1,
+        }];
+        assert_eq!(delta.delta, expected);
+        remove_file(tmp_in_file).unwrap();
+    }
+
+    #[test]
+    fn test_gen_delta_long_literal() {
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        write(&tmp_in_file, "abcdefgh").unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, 8, "adler", SignMode::Lean).unwrap();
+
+        // Literal runs are split once they reach the limit
+        let data: Vec<u8> = (0..MAX_LITERAL_LEN * 2 + 10)
+            .map(|i| (i % 7) as u8)
+            .collect();
+        let mut output = Vec::new();
+        gen_delta(data.as_slice(), &mut output, &signatures).unwrap();
+        let delta = read_delta(output.as_slice()).unwrap();
+        assert_eq!(delta.delta.len(), 3);
+        let mut joined = Vec::new();
+        for op in delta.delta {
+            match op {
+                Delta::Literal(run) => {
+                    assert!(run.len() <= MAX_LITERAL_LEN);
+                    joined.extend(run);
+                }
+                _ => panic!("Expected only literals"),
+            }
+        }
+        assert_eq!(joined, data);
+        remove_file(tmp_in_file).unwrap();
+    }
+
+    #[test]
+    fn test_gen_delta_chunked() {
+        let mut state = 7;
+        let data: Vec<u8> = (0..64 * 1024)
+            .map(|_| splitmix64(&mut state) as u8)
+            .collect();
+        let (signatures, _) = Signature::gen_sigs_chunked(
+            data.as_slice(),
+            256,
+            Chunking::gear(256),
+            "adler",
+            "blake3",
+            SignMode::Lean,
+        )
+        .unwrap();
+
+        // An insertion near the start only costs the blocks around it
+        let mut modified = data[..1000].to_vec();
+        modified.extend_from_slice(b"inserted");
+        modified.extend_from_slice(&data[1000..]);
+        let mut output = Vec::new();
+        gen_delta(modified.as_slice(), &mut output, &signatures).unwrap();
+        let delta = read_delta(output.as_slice()).unwrap();
+
+        let mut patched = Vec::new();
+        let mut literal_len = 0;
+        for op in delta.delta {
+            match op {
+                Delta::Literal(run) => {
+                    literal_len += run.len();
+                    patched.extend(run);
+                }
+                Delta::CopyRange { offset, len } => {
+                    patched.extend_from_slice(&data[offset as usize..(offset + len) as usize]);
+                }
+                _ => panic!("Expected only literals and ranges"),
+            }
+        }
+        assert_eq!(patched, modified);
+        assert!(literal_len < 4 * 1024);
+    }
+
     #[test]
     fn test_calculate_rolling_hashes_all() {
         let max_chunk_size = 8;
@@ -190,61 +824,53 @@ mod test {
         let modified_data = "He stepped readily onto the bridge knowing that enchantment awaited on the other side. The teens wondered what was kept in the black shed on the far edge of the high school grounds.";
         write(&tmp_m_in_file, modified_data).unwrap();
         // Generate the delta
-        let (signatures, _) = Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm).unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Lean).unwrap();
         gen_delta_from_file(
             &tmp_m_in_file,
-            chunk_size,
-            algorithm,
             &tmp_out_file,
+            Format::Json,
             signatures.clone(),
         )
         .unwrap();
 
         // Get the hashes
         let buffer = modified_data.as_bytes().to_vec();
-        let hashes = match algorithm {
-            "fletcher" => {
-                let algo = Fletcher32::new();
-                calculate_rolling_hashes(chunk_size, algo, &buffer).unwrap()
-            }
-
-            _ => {
-                let algo = Adler32::new();
-                calculate_rolling_hashes(chunk_size, algo, &buffer).unwrap()
-            }
-        };
+        let hashes = get_chunk_hashes(&buffer, chunk_size, algorithm);
 
         // Load the delta from file
-        let f = File::open(&tmp_out_file).unwrap();
-        let reader = BufReader::new(f);
-        let loaded_delta: Vec<Delta> = serde_json::from_reader(reader).unwrap();
+        let loaded_delta = load_delta(&tmp_out_file).unwrap();
 
         // Ensure they exist in the file
         let mut index = 0;
         while index < buffer.len() {
-            let chunk = &buffer[index..];
             if index > buffer.len() - chunk_size {
                 break;
             }
-            let curr_hash = hashes[index].weak_hash;
-            if signatures.contains_key(&curr_hash) {
-                let checksum = &signatures.get(&curr_hash).unwrap().checksum;
+            let chunk = &buffer[index..index + chunk_size];
+            let curr_hash = hashes[index];
+            if let Some(signs) = signatures.signatures.get(&curr_hash) {
                 let this_checksum = get_blake2(chunk.to_vec()).unwrap();
-                if checksum == &this_checksum {
-                    let chunk_index = signatures.get(&curr_hash).unwrap().index;
-                    let res = loaded_delta.iter().find(|dt| {
-                        if let Delta::I(i) = dt {
-                            return i == &chunk_index;
+                if let Some(sign) = signs.iter().find(|sign| sign.checksum == this_checksum) {
+                    let chunk_index = sign.index;
+                    let res = loaded_delta.delta.iter().find(|dt| {
+                        if let Delta::Copy {
+                            start_block,
+                            block_count,
+                        } = dt
+                        {
+                            return (*start_block..start_block + block_count)
+                                .contains(&chunk_index);
                         }
                         false
                     });
                     // Actual test to see delta is there
                     assert_ne!(res, None);
-                    index = index + chunk_size;
+                    index += chunk_size;
                     continue;
                 }
             }
-            index = index + 1;
+            index += 1;
         }
 
         // Cleanup
@@ -255,56 +881,31 @@ mod test {
 
     fn test_calculate_rolling_hashes(chunk_size: usize, algorithm: &str) {
         let data = "hello world how are we".as_bytes().to_vec();
-
-        match algorithm {
-            "fletcher" => {
-                let algo = Fletcher32::new();
-                let hashes = calculate_rolling_hashes(chunk_size, algo, &data).unwrap();
-                let chunk_hashes = get_chunk_hashes(data, chunk_size, algorithm).unwrap();
-                for (index, hashblock) in chunk_hashes.iter().enumerate() {
-                    assert_eq!(hashblock.weak_hash, hashes[index].weak_hash);
-                    assert_eq!(hashblock.bytes, hashes[index].bytes);
-                }
-            }
-
-            _ => {
-                let algo = Adler32::new();
-                let hashes = calculate_rolling_hashes(chunk_size, algo, &data).unwrap();
-                let chunk_hashes = get_chunk_hashes(data, chunk_size, "adler").unwrap();
-                for (index, hashblock) in chunk_hashes.iter().enumerate() {
-                    assert_eq!(hashblock.weak_hash, hashes[index].weak_hash);
-                    assert_eq!(hashblock.bytes, hashes[index].bytes);
-                }
-            }
-        }
+        let chunk_hashes = get_chunk_hashes(&data, chunk_size, algorithm);
+        let algo = registry::create(algorithm).unwrap();
+        let hashes = get_rolling_hashes(&data, chunk_size, algo);
+        assert_eq!(hashes, chunk_hashes);
     }
 
-    fn get_chunk_hashes(
-        data: Vec<u8>,
+    fn get_rolling_hashes(
+        data: &[u8],
         chunk_size: usize,
-        algorithm: &str,
-    ) -> Result<Vec<HashBlock>, DiffError> {
-        let mut hashblocklist = Vec::new();
-        for index in 0..data.len() {
-            if index + chunk_size > data.len() {
-                break;
-            }
-            let chunk = &data[index..index + chunk_size];
-
-            let weak_hash = match algorithm {
-                "fletcher" => Fletcher32::new().get_chunk_hash(chunk).unwrap(),
-                _ => Adler32::new().get_chunk_hash(chunk).unwrap(),
-            };
-
-            let hash_block = HashBlock {
-                index: index as u32,
-                weak_hash,
-                bytes: chunk.to_vec(),
-            };
-            hashblocklist.push(hash_block);
+        mut algo: Box<dyn Algorithm>,
+    ) -> Vec<u32> {
+        let mut hashes = vec![algo.get_chunk_hash(&data[..chunk_size]).unwrap()];
+        for byte in &data[chunk_size..] {
+            hashes.push(algo.get_rolling_hash(byte).unwrap());
         }
+        hashes
+    }
 
-        Ok(hashblocklist)
+    fn get_chunk_hashes(data: &[u8], chunk_size: usize, algorithm: &str) -> Vec<u32> {
+        data.windows(chunk_size)
+            .map(|chunk| {
+                let mut algo = registry::create(algorithm).unwrap();
+                algo.get_chunk_hash(chunk).unwrap()
+            })
+            .collect()
     }
 
     fn get_rnum() -> u32 {
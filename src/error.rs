@@ -1,8 +1,105 @@
+use std::fs::File;
+use std::path::Path;
 use thiserror::Error;
 #[derive(Error, Debug)]
 pub enum DiffError {
-    #[error("io error")]
+    #[error("io error: {0}")]
     IO(#[from] std::io::Error),
-    #[error("serialization error")]
+    /// An io error on a named file
+    #[error("{path}: {source}")]
+    File {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("serialization error: {0}")]
     SE(#[from] serde_json::Error),
+    #[error("format error: {0}")]
+    Format(String),
+    /// Binary signatures or delta that don't decode, `offset` is how far
+    /// the reader had got
+    #[error("corrupt {kind} at byte {offset}: {reason}")]
+    Corrupt {
+        kind: &'static str,
+        offset: u64,
+        reason: String,
+    },
+    /// Binary signatures or delta that end before they should
+    #[error("truncated {kind}, it ends at byte {offset}")]
+    Truncated { kind: &'static str, offset: u64 },
+    /// A delta instruction that points outside of the basis file
+    #[error("missing block: {len} bytes at offset {offset} are past the end of the {basis_len} byte basis")]
+    MissingBlock {
+        offset: u64,
+        len: u64,
+        basis_len: u64,
+    },
+    /// The basis isn't the file the delta was made against, `path` is
+    /// filled in when patching files
+    #[error("{}basis file mismatch: {reason}", path_prefix(.path))]
+    BasisMismatch {
+        path: Option<String>,
+        reason: String,
+    },
+    /// The patched output doesn't match the digest in the delta, `path` is
+    /// the output file when patching files
+    #[error("{}checksum mismatch: {reason}", path_prefix(.path))]
+    ChecksumMismatch {
+        path: Option<String>,
+        reason: String,
+    },
+    #[error("unknown rolling hash algorithm: {0}")]
+    UnknownAlgorithm(String),
+    #[error("rolling hash algorithm {0} is already registered")]
+    AlgorithmExists(String),
+    #[error("invalid algorithm parameters: {0}")]
+    AlgorithmParams(String),
+    #[error("unknown strong hash: {0}")]
+    UnknownStrongHash(String),
+    #[error("invalid chunk size: {0}")]
+    InvalidChunkSize(String),
+    #[error("invalid chunking: {0}")]
+    Chunking(String),
+}
+
+impl DiffError {
+    /// Attach the path to an io error
+    pub fn file(path: impl AsRef<Path>, source: std::io::Error) -> Self {
+        DiffError::File {
+            path: path.as_ref().display().to_string(),
+            source,
+        }
+    }
+
+    /// Name the basis and output files in a mismatch found while patching
+    pub(crate) fn with_paths(self, basis: &str, output: &str) -> Self {
+        match self {
+            DiffError::BasisMismatch { reason, .. } => DiffError::BasisMismatch {
+                path: Some(basis.to_string()),
+                reason,
+            },
+            DiffError::ChecksumMismatch { reason, .. } => DiffError::ChecksumMismatch {
+                path: Some(output.to_string()),
+                reason,
+            },
+            err => err,
+        }
+    }
+}
+
+fn path_prefix(path: &Option<String>) -> String {
+    match path {
+        Some(path) => format!("{}: ", path),
+        None => String::new(),
+    }
+}
+
+/// Open a file for reading, errors name the file
+pub fn open_file(path: impl AsRef<Path>) -> Result<File, DiffError> {
+    File::open(&path).map_err(|err| DiffError::file(path, err))
+}
+
+/// Create or truncate a file for writing, errors name the file
+pub fn create_file(path: impl AsRef<Path>) -> Result<File, DiffError> {
+    File::create(&path).map_err(|err| DiffError::file(path, err))
 }
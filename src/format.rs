@@ -0,0 +1,896 @@
+use crate::chunking::Chunking;
+use crate::delta::{Delta, DeltaSet, DeltaSink};
+use crate::error::{create_file, open_file, DiffError};
+use crate::header::Header;
+use crate::sign::{Signature, SignatureIndex, SignatureSet};
+use serde_json;
+use std::io::{BufReader, BufWriter, Chain, Cursor, ErrorKind, Read, Write};
+
+pub const SIGNATURE_MAGIC: [u8; 4] = *b"RDSG";
+pub const DELTA_MAGIC: [u8; 4] = *b"RDDL";
+pub const FORMAT_VERSION: u8 = 1;
+
+// Signature flags
+const FLAG_BLOCK_BYTES: u8 = 0x01;
+const FLAG_BLOCK_RANGES: u8 = 0x02;
+
+// Chunking tags
+const CHUNKING_FIXED: u8 = 0x00;
+const CHUNKING_GEAR: u8 = 0x01;
+
+// Delta instruction tags
+const TAG_END: u8 = 0x00;
+const TAG_LITERAL: u8 = 0x01;
+const TAG_COPY: u8 = 0x02;
+const TAG_COPY_RANGE: u8 = 0x03;
+
+/// How signatures and deltas are written to disk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Compact versioned binary container
+    Binary,
+    /// Human readable JSON, handy for debugging
+    Json,
+}
+
+/// Write the signatures to the file in the given format
+pub fn save_signatures(
+    path: &str,
+    signatures: &SignatureSet,
+    format: Format,
+) -> Result<(), DiffError> {
+    let mut writer = BufWriter::new(create_file(path)?);
+    write_signatures(&mut writer, signatures, format)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Load the signatures from the file, the format is detected automatically
+pub fn load_signatures(path: &str) -> Result<SignatureSet, DiffError> {
+    let reader = BufReader::new(open_file(path)?);
+    read_signatures(reader)
+}
+
+/// Write the delta to the file in the given format
+pub fn save_delta(path: &str, delta: &DeltaSet, format: Format) -> Result<(), DiffError> {
+    let mut writer = BufWriter::new(create_file(path)?);
+    write_delta(&mut writer, delta, format)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Load the delta from the file, the format is detected automatically
+pub fn load_delta(path: &str) -> Result<DeltaSet, DiffError> {
+    let reader = BufReader::new(open_file(path)?);
+    read_delta(reader)
+}
+
+pub fn write_signatures<W: Write>(
+    mut writer: W,
+    signature_set: &SignatureSet,
+    format: Format,
+) -> Result<(), DiffError> {
+    if format == Format::Json {
+        serde_json::to_writer(writer, signature_set)?;
+        return Ok(());
+    }
+
+    // Sort by block index so the same file always gives the same output
+    let mut entries: Vec<(u32, &Signature)> = signature_set
+        .signatures
+        .iter()
+        .flat_map(|(weak_hash, signs)| signs.iter().map(move |sign| (*weak_hash, sign)))
+        .collect();
+    entries.sort_by_key(|(_, sign)| sign.index);
+
+    let has_bytes = entries.iter().any(|(_, sign)| sign.bytes.is_some());
+    // Fixed blocks can be located from their index alone
+    let has_ranges = signature_set.header.chunking != Chunking::Fixed;
+    let mut flags = 0;
+    if has_bytes {
+        flags |= FLAG_BLOCK_BYTES;
+    }
+    if has_ranges {
+        flags |= FLAG_BLOCK_RANGES;
+    }
+
+    writer.write_all(&SIGNATURE_MAGIC)?;
+    writer.write_all(&[FORMAT_VERSION, flags])?;
+    write_header(&mut writer, &signature_set.header)?;
+    write_varint(&mut writer, entries.len() as u64)?;
+    for (weak_hash, sign) in entries {
+        write_varint(&mut writer, sign.index)?;
+        writer.write_all(&weak_hash.to_le_bytes())?;
+        write_bytes(&mut writer, &sign.checksum)?;
+        if has_ranges {
+            write_varint(&mut writer, sign.offset)?;
+            write_varint(&mut writer, sign.len)?;
+        }
+        if has_bytes {
+            let bytes = sign.bytes.as_deref().unwrap_or_default();
+            write_bytes(&mut writer, bytes)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn read_signatures<R: Read>(reader: R) -> Result<SignatureSet, DiffError> {
+    let (is_binary, reader) = detect(reader, &SIGNATURE_MAGIC)?;
+    if !is_binary {
+        let signatures: SignatureSet = serde_json::from_reader(reader)?;
+        signatures.header.validate()?;
+        return Ok(signatures);
+    }
+
+    let mut reader = Counted::new(reader, SIGNATURE_MAGIC.len() as u64);
+    read_binary_signatures(&mut reader).map_err(|err| locate(err, "signatures", reader.count))
+}
+
+fn read_binary_signatures<R: Read>(mut reader: R) -> Result<SignatureSet, DiffError> {
+    let flags = read_version(&mut reader)?;
+    let header = read_header(&mut reader)?;
+    let count = read_varint(&mut reader)?;
+    let mut signatures = SignatureIndex::new();
+    for _ in 0..count {
+        let index = read_varint(&mut reader)?;
+        let mut weak_hash = [0; 4];
+        reader.read_exact(&mut weak_hash)?;
+        let checksum = read_bytes(&mut reader)?;
+        let (offset, len) = match flags & FLAG_BLOCK_RANGES {
+            0 => header.block_range(index, 1),
+            _ => (read_varint(&mut reader)?, read_varint(&mut reader)?),
+        };
+        let bytes = match flags & FLAG_BLOCK_BYTES {
+            0 => None,
+            _ => Some(read_bytes(&mut reader)?),
+        };
+        let sign = Signature {
+            index,
+            offset,
+            len,
+            checksum,
+            bytes,
+        };
+        signatures
+            .entry(u32::from_le_bytes(weak_hash))
+            .or_default()
+            .push(sign);
+    }
+
+    Ok(SignatureSet { header, signatures })
+}
+
+pub fn write_delta<W: Write>(
+    writer: W,
+    delta_set: &DeltaSet,
+    format: Format,
+) -> Result<(), DiffError> {
+    if format == Format::Json {
+        serde_json::to_writer(writer, delta_set)?;
+        return Ok(());
+    }
+
+    let mut delta_writer = DeltaWriter::new(writer, &delta_set.header)?;
+    for op in &delta_set.delta {
+        delta_writer.write_op(op)?;
+    }
+    delta_writer.finish(&delta_set.target_digest)?;
+
+    Ok(())
+}
+
+/// Writes a binary delta one instruction at a time, so the instructions
+/// never have to be collected in memory
+pub struct DeltaWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> DeltaWriter<W> {
+    /// Start the delta by writing the magic, version and header
+    pub fn new(mut writer: W, header: &Header) -> Result<Self, DiffError> {
+        writer.write_all(&DELTA_MAGIC)?;
+        writer.write_all(&[FORMAT_VERSION, 0])?;
+        write_header(&mut writer, header)?;
+        Ok(DeltaWriter { writer })
+    }
+
+    pub fn write_op(&mut self, op: &Delta) -> Result<(), DiffError> {
+        write_op(&mut self.writer, op)
+    }
+
+    /// End the instructions and write the digest of the target file
+    pub fn finish(mut self, target_digest: &[u8]) -> Result<W, DiffError> {
+        self.write_end(target_digest)?;
+        Ok(self.writer)
+    }
+
+    fn write_end(&mut self, target_digest: &[u8]) -> Result<(), DiffError> {
+        self.writer.write_all(&[TAG_END])?;
+        write_bytes(&mut self.writer, target_digest)
+    }
+}
+
+impl<W: Write> DeltaSink for DeltaWriter<W> {
+    fn push_op(&mut self, op: Delta) -> Result<(), DiffError> {
+        self.write_op(&op)
+    }
+
+    fn push_digest(&mut self, target_digest: Vec<u8>) -> Result<(), DiffError> {
+        self.write_end(&target_digest)
+    }
+}
+
+/// Writes a JSON delta. JSON can't be written a piece at a time, so the
+/// instructions are collected and written out along with the digest.
+pub struct JsonDeltaWriter<W: Write> {
+    writer: W,
+    delta_set: DeltaSet,
+}
+
+impl<W: Write> JsonDeltaWriter<W> {
+    pub fn new(writer: W, header: &Header) -> Self {
+        JsonDeltaWriter {
+            writer,
+            delta_set: DeltaSet::new(header.clone()),
+        }
+    }
+}
+
+impl<W: Write> DeltaSink for JsonDeltaWriter<W> {
+    fn push_op(&mut self, op: Delta) -> Result<(), DiffError> {
+        self.delta_set.push_op(op)
+    }
+
+    fn push_digest(&mut self, target_digest: Vec<u8>) -> Result<(), DiffError> {
+        self.delta_set.push_digest(target_digest)?;
+        serde_json::to_writer(&mut self.writer, &self.delta_set)?;
+        Ok(())
+    }
+}
+
+/// Sink that encodes the delta to the writer in the given format
+pub fn delta_sink<'a, W: Write + 'a>(
+    writer: W,
+    header: &Header,
+    format: Format,
+) -> Result<Box<dyn DeltaSink + 'a>, DiffError> {
+    match format {
+        Format::Binary => Ok(Box::new(DeltaWriter::new(writer, header)?)),
+        Format::Json => Ok(Box::new(JsonDeltaWriter::new(writer, header))),
+    }
+}
+
+pub fn read_delta<R: Read>(reader: R) -> Result<DeltaSet, DiffError> {
+    let mut delta_reader = DeltaReader::new(reader)?;
+    let mut delta = Vec::new();
+    while let Some(op) = delta_reader.next_op()? {
+        delta.push(op);
+    }
+    let (header, target_digest) = delta_reader.finish()?;
+
+    Ok(DeltaSet {
+        header,
+        delta,
+        target_digest,
+    })
+}
+
+/// Reads a delta one instruction at a time. Binary deltas are decoded as
+/// they are read, JSON deltas are only meant for debugging and get loaded
+/// in one go.
+pub struct DeltaReader<R: Read> {
+    header: Header,
+    source: DeltaSource<R>,
+    target_digest: Option<Vec<u8>>,
+}
+
+enum DeltaSource<R: Read> {
+    Binary(Counted<Detected<R>>),
+    Json(std::vec::IntoIter<Delta>),
+}
+
+impl<R: Read> DeltaReader<R> {
+    /// Check the magic and version and read the header, the format is
+    /// detected automatically
+    pub fn new(reader: R) -> Result<Self, DiffError> {
+        let (is_binary, reader) = detect(reader, &DELTA_MAGIC)?;
+        if !is_binary {
+            let delta_set: DeltaSet = serde_json::from_reader(reader)?;
+            delta_set.header.validate()?;
+            return Ok(DeltaReader {
+                header: delta_set.header,
+                source: DeltaSource::Json(delta_set.delta.into_iter()),
+                target_digest: Some(delta_set.target_digest),
+            });
+        }
+
+        let mut reader = Counted::new(reader, DELTA_MAGIC.len() as u64);
+        let header = read_version(&mut reader)
+            .and_then(|_| read_header(&mut reader))
+            .map_err(|err| locate(err, "delta", reader.count))?;
+        Ok(DeltaReader {
+            header,
+            source: DeltaSource::Binary(reader),
+            target_digest: None,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Read the next instruction, `None` once they have all been read
+    pub fn next_op(&mut self) -> Result<Option<Delta>, DiffError> {
+        match &mut self.source {
+            DeltaSource::Json(ops) => Ok(ops.next()),
+            DeltaSource::Binary(reader) => {
+                if self.target_digest.is_some() {
+                    return Ok(None);
+                }
+                let op = read_op(reader).map_err(|err| locate(err, "delta", reader.count))?;
+                if op.is_none() {
+                    let target_digest =
+                        read_bytes(reader).map_err(|err| locate(err, "delta", reader.count))?;
+                    self.target_digest = Some(target_digest);
+                }
+                Ok(op)
+            }
+        }
+    }
+
+    /// Header and digest of the target file, only available once every
+    /// instruction has been read
+    pub fn finish(self) -> Result<(Header, Vec<u8>), DiffError> {
+        let complete = match &self.source {
+            DeltaSource::Json(ops) => ops.len() == 0,
+            DeltaSource::Binary(_) => self.target_digest.is_some(),
+        };
+        match self.target_digest {
+            Some(target_digest) if complete => Ok((self.header, target_digest)),
+            _ => Err(DiffError::Format(
+                "delta instructions were not read to the end".to_string(),
+            )),
+        }
+    }
+}
+
+fn write_header<W: Write>(writer: &mut W, header: &Header) -> Result<(), DiffError> {
+    write_varint(writer, header.chunk_size as u64)?;
+    match header.chunking {
+        Chunking::Fixed => writer.write_all(&[CHUNKING_FIXED])?,
+        Chunking::Gear { min, max } => {
+            writer.write_all(&[CHUNKING_GEAR])?;
+            write_varint(writer, min as u64)?;
+            write_varint(writer, max as u64)?;
+        }
+    }
+    write_bytes(writer, header.algorithm.as_bytes())?;
+    write_bytes(writer, header.strong_hash.as_bytes())?;
+    write_varint(writer, header.basis_len)?;
+    write_bytes(writer, &header.basis_digest)?;
+    Ok(())
+}
+
+fn read_header<R: Read>(reader: &mut R) -> Result<Header, DiffError> {
+    let chunk_size = read_usize(reader)?;
+    let mut tag = [0; 1];
+    reader.read_exact(&mut tag)?;
+    let chunking = match tag[0] {
+        CHUNKING_FIXED => Chunking::Fixed,
+        CHUNKING_GEAR => Chunking::Gear {
+            min: read_usize(reader)?,
+            max: read_usize(reader)?,
+        },
+        other => return Err(DiffError::Format(format!("unknown chunking {}", other))),
+    };
+    let header = Header {
+        chunk_size,
+        chunking,
+        algorithm: read_string(reader)?,
+        strong_hash: read_string(reader)?,
+        basis_len: read_varint(reader)?,
+        basis_digest: read_bytes(reader)?,
+    };
+    header.validate()?;
+    Ok(header)
+}
+
+fn write_op<W: Write>(writer: &mut W, op: &Delta) -> Result<(), DiffError> {
+    match op {
+        Delta::Literal(bytes) => {
+            writer.write_all(&[TAG_LITERAL])?;
+            write_bytes(writer, bytes)?;
+        }
+        Delta::Copy {
+            start_block,
+            block_count,
+        } => {
+            writer.write_all(&[TAG_COPY])?;
+            write_varint(writer, *start_block)?;
+            write_varint(writer, *block_count)?;
+        }
+        Delta::CopyRange { offset, len } => {
+            writer.write_all(&[TAG_COPY_RANGE])?;
+            write_varint(writer, *offset)?;
+            write_varint(writer, *len)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read the next delta instruction, `None` once the end marker is reached
+fn read_op<R: Read>(reader: &mut R) -> Result<Option<Delta>, DiffError> {
+    let mut tag = [0; 1];
+    reader.read_exact(&mut tag)?;
+    let op = match tag[0] {
+        TAG_END => return Ok(None),
+        TAG_LITERAL => Delta::Literal(read_bytes(reader)?),
+        TAG_COPY => Delta::Copy {
+            start_block: read_varint(reader)?,
+            block_count: read_varint(reader)?,
+        },
+        TAG_COPY_RANGE => Delta::CopyRange {
+            offset: read_varint(reader)?,
+            len: read_varint(reader)?,
+        },
+        other => return Err(DiffError::Format(format!("unknown instruction {}", other))),
+    };
+    Ok(Some(op))
+}
+
+/// Reader with the bytes used for format detection put back in front
+type Detected<R> = Chain<Cursor<Vec<u8>>, R>;
+
+/// Check if the reader starts with the magic number. The bytes looked at are
+/// handed back in front of the returned reader when it's not a binary file.
+fn detect<R: Read>(mut reader: R, magic: &[u8; 4]) -> Result<(bool, Detected<R>), DiffError> {
+    let mut prefix = Vec::with_capacity(magic.len());
+    (&mut reader)
+        .take(magic.len() as u64)
+        .read_to_end(&mut prefix)?;
+    if prefix == magic {
+        return Ok((true, Cursor::new(Vec::new()).chain(reader)));
+    }
+    Ok((false, Cursor::new(prefix).chain(reader)))
+}
+
+/// Keeps count of the bytes read, so decoding errors can say where they are
+struct Counted<R: Read> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Counted<R> {
+    /// Start counting from `count`, the bytes already read
+    fn new(inner: R, count: u64) -> Self {
+        Counted { inner, count }
+    }
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+/// Turn a decoding error into one that says what was being read and how
+/// far the reader had got
+fn locate(err: DiffError, kind: &'static str, offset: u64) -> DiffError {
+    match err {
+        DiffError::Format(reason) => DiffError::Corrupt {
+            kind,
+            offset,
+            reason,
+        },
+        DiffError::IO(err) if err.kind() == ErrorKind::UnexpectedEof => {
+            DiffError::Truncated { kind, offset }
+        }
+        err => err,
+    }
+}
+
+/// Check the format version, returns the flags byte that follows it
+fn read_version<R: Read>(reader: &mut R) -> Result<u8, DiffError> {
+    let mut version = [0; 2];
+    reader.read_exact(&mut version)?;
+    if version[0] != FORMAT_VERSION {
+        return Err(DiffError::Format(format!(
+            "unsupported format version {}",
+            version[0]
+        )));
+    }
+    Ok(version[1])
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<(), DiffError> {
+    write_varint(writer, bytes.len() as u64)?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>, DiffError> {
+    let len = read_varint(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(DiffError::IO(ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(bytes)
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String, DiffError> {
+    String::from_utf8(read_bytes(reader)?)
+        .map_err(|_| DiffError::Format("string is not valid utf-8".to_string()))
+}
+
+fn read_usize<R: Read>(reader: &mut R) -> Result<usize, DiffError> {
+    let value = read_varint(reader)?;
+    usize::try_from(value).map_err(|_| DiffError::Format(format!("{} is out of range", value)))
+}
+
+/// Write an unsigned LEB128 varint
+pub fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> Result<(), DiffError> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            writer.write_all(&[byte])?;
+            return Ok(());
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+/// Read an unsigned LEB128 varint
+pub fn read_varint<R: Read>(reader: &mut R) -> Result<u64, DiffError> {
+    let mut value = 0u64;
+    let mut byte = [0; 1];
+    for shift in (0..64).step_by(7) {
+        reader.read_exact(&mut byte)?;
+        let bits = (byte[0] & 0x7f) as u64;
+        if shift == 63 && bits > 1 {
+            break;
+        }
+        value |= bits << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(DiffError::Format("varint is too long".to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        let values = [0, 1, 127, 128, 300, 16384, u32::MAX as u64, u64::MAX];
+        for value in values {
+            let mut buffer = Vec::new();
+            write_varint(&mut buffer, value).unwrap();
+            let decoded = read_varint(&mut buffer.as_slice()).unwrap();
+            assert_eq!(value, decoded);
+        }
+        // Small values only take a single byte
+        let mut buffer = Vec::new();
+        write_varint(&mut buffer, 127).unwrap();
+        assert_eq!(buffer, vec![127]);
+    }
+
+    #[test]
+    fn test_varint_too_long() {
+        let buffer = [0xff; 11];
+        assert!(matches!(
+            read_varint(&mut buffer.as_slice()),
+            Err(DiffError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn test_delta_binary() {
+        let delta = get_delta();
+        let mut buffer = Vec::new();
+        write_delta(&mut buffer, &delta, Format::Binary).unwrap();
+        assert_eq!(buffer[..4], DELTA_MAGIC);
+        assert_eq!(buffer[4], 1);
+
+        let loaded = read_delta(buffer.as_slice()).unwrap();
+        assert_eq!(delta, loaded);
+
+        // Much smaller than the JSON version
+        let mut json = Vec::new();
+        write_delta(&mut json, &delta, Format::Json).unwrap();
+        assert!(buffer.len() < json.len() / 2);
+    }
+
+    #[test]
+    fn test_delta_json() {
+        let delta = get_delta();
+        let mut buffer = Vec::new();
+        write_delta(&mut buffer, &delta, Format::Json).unwrap();
+        let loaded = read_delta(buffer.as_slice()).unwrap();
+        assert_eq!(delta, loaded);
+    }
+
+    #[test]
+    fn test_delta_truncated() {
+        let delta = get_delta();
+        let mut buffer = Vec::new();
+        write_delta(&mut buffer, &delta, Format::Binary).unwrap();
+        for len in 0..buffer.len() {
+            let result = read_delta(&buffer[..len]);
+            match len < DELTA_MAGIC.len() {
+                // Too short to tell it's binary, so it doesn't parse as JSON
+                true => assert!(matches!(result, Err(DiffError::SE(_)))),
+                false => assert!(matches!(
+                    result,
+                    Err(DiffError::Truncated { kind: "delta", offset }) if offset == len as u64
+                )),
+            }
+        }
+    }
+
+    #[test]
+    fn test_delta_reader() {
+        let delta = get_delta();
+        for format in [Format::Binary, Format::Json] {
+            let mut buffer = Vec::new();
+            write_delta(&mut buffer, &delta, format).unwrap();
+
+            let mut delta_reader = DeltaReader::new(buffer.as_slice()).unwrap();
+            assert_eq!(delta_reader.header(), &delta.header);
+            assert_eq!(
+                delta_reader.next_op().unwrap().as_ref(),
+                delta.delta.first()
+            );
+            // Stopping early doesn't give out the digest
+            assert!(delta_reader.finish().is_err());
+
+            let mut delta_reader = DeltaReader::new(buffer.as_slice()).unwrap();
+            for op in &delta.delta {
+                assert_eq!(delta_reader.next_op().unwrap().as_ref(), Some(op));
+            }
+            assert_eq!(delta_reader.next_op().unwrap(), None);
+            let (header, target_digest) = delta_reader.finish().unwrap();
+            assert_eq!(header, delta.header);
+            assert_eq!(target_digest, delta.target_digest);
+        }
+    }
+
+    #[test]
+    fn test_delta_sink() {
+        let delta = get_delta();
+        for format in [Format::Binary, Format::Json] {
+            let mut buffer = Vec::new();
+            let mut sink = delta_sink(&mut buffer, &delta.header, format).unwrap();
+            for op in &delta.delta {
+                sink.push_op(op.clone()).unwrap();
+            }
+            sink.push_digest(delta.target_digest.clone()).unwrap();
+            drop(sink);
+
+            // Same bytes as writing the whole delta in one go
+            let mut expected = Vec::new();
+            write_delta(&mut expected, &delta, format).unwrap();
+            assert_eq!(buffer, expected);
+        }
+    }
+
+    #[test]
+    fn test_delta_bad_version() {
+        let mut buffer = Vec::new();
+        write_delta(&mut buffer, &get_delta(), Format::Binary).unwrap();
+        for version in [0, 2, 4] {
+            buffer[4] = version;
+            assert!(matches!(
+                read_delta(buffer.as_slice()),
+                Err(DiffError::Corrupt {
+                    kind: "delta",
+                    offset: 6,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_signatures_binary() {
+        for with_bytes in [true, false] {
+            let signatures = get_signatures(with_bytes);
+            let mut buffer = Vec::new();
+            write_signatures(&mut buffer, &signatures, Format::Binary).unwrap();
+            assert_eq!(buffer[..4], SIGNATURE_MAGIC);
+            assert_eq!(buffer[4], 1);
+
+            let loaded = read_signatures(buffer.as_slice()).unwrap();
+            assert_signatures_eq(&signatures, &loaded);
+        }
+    }
+
+    #[test]
+    fn test_signatures_corrupt() {
+        let mut buffer = Vec::new();
+        write_signatures(&mut buffer, &get_signatures(true), Format::Binary).unwrap();
+        let half = buffer.len() / 2;
+        assert!(matches!(
+            read_signatures(&buffer[..half]),
+            Err(DiffError::Truncated { kind: "signatures", offset }) if offset == half as u64
+        ));
+
+        // Magic, version, flags and the two byte chunk size come first
+        buffer[8] = 0x7f;
+        let err = read_signatures(buffer.as_slice()).err().unwrap();
+        assert!(matches!(
+            err,
+            DiffError::Corrupt {
+                kind: "signatures",
+                offset: 9,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "corrupt signatures at byte 9: unknown chunking 127"
+        );
+    }
+
+    #[test]
+    fn test_header_invalid() {
+        // Block sizes signing would never have used are corrupt
+        let mut signatures = get_signatures(false);
+        signatures.header.chunk_size = 0;
+        let mut buffer = Vec::new();
+        write_signatures(&mut buffer, &signatures, Format::Binary).unwrap();
+        let err = read_signatures(buffer.as_slice()).err().unwrap();
+        assert!(matches!(
+            err,
+            DiffError::Corrupt {
+                kind: "signatures",
+                ..
+            }
+        ));
+        assert!(err.to_string().contains("invalid chunk size"));
+
+        let mut delta = get_delta();
+        delta.header.chunking = Chunking::Gear {
+            min: 1,
+            max: usize::MAX,
+        };
+        let mut buffer = Vec::new();
+        write_delta(&mut buffer, &delta, Format::Binary).unwrap();
+        assert!(matches!(
+            read_delta(buffer.as_slice()),
+            Err(DiffError::Corrupt { kind: "delta", .. })
+        ));
+        buffer.clear();
+        write_delta(&mut buffer, &delta, Format::Json).unwrap();
+        assert!(matches!(
+            read_delta(buffer.as_slice()),
+            Err(DiffError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn test_signatures_gear() {
+        // Content defined blocks keep their ranges
+        let mut signatures = get_signatures(false);
+        signatures.header.chunking = Chunking::gear(1024);
+        for sign in signatures.signatures.values_mut().flatten() {
+            sign.offset = sign.index * 700 + 3;
+            sign.len = 700 + sign.index;
+        }
+        let mut buffer = Vec::new();
+        write_signatures(&mut buffer, &signatures, Format::Binary).unwrap();
+        let loaded = read_signatures(buffer.as_slice()).unwrap();
+        assert_signatures_eq(&signatures, &loaded);
+    }
+
+    #[test]
+    fn test_signatures_json() {
+        let signatures = get_signatures(false);
+        let mut buffer = Vec::new();
+        write_signatures(&mut buffer, &signatures, Format::Json).unwrap();
+        let loaded = read_signatures(buffer.as_slice()).unwrap();
+        assert_signatures_eq(&signatures, &loaded);
+    }
+
+    #[test]
+    fn test_signatures_deterministic() {
+        // Insertion order of the hashmap should not change the output
+        let signatures = get_signatures(false);
+        let mut reversed = SignatureSet {
+            header: signatures.header.clone(),
+            signatures: SignatureIndex::new(),
+        };
+        let mut entries: Vec<_> = signatures.signatures.iter().collect();
+        entries.reverse();
+        for (weak_hash, signs) in entries {
+            reversed.signatures.insert(*weak_hash, signs.clone());
+        }
+        let mut first = Vec::new();
+        write_signatures(&mut first, &signatures, Format::Binary).unwrap();
+        let mut second = Vec::new();
+        write_signatures(&mut second, &reversed, Format::Binary).unwrap();
+        assert_eq!(first, second);
+    }
+
+    fn get_header() -> Header {
+        Header {
+            chunk_size: 1024,
+            chunking: Chunking::Fixed,
+            algorithm: "fletcher".to_string(),
+            strong_hash: "blake2s256".to_string(),
+            basis_len: 5_000_000_000,
+            basis_digest: vec![7; 32],
+        }
+    }
+
+    fn get_delta() -> DeltaSet {
+        let delta = vec![
+            Delta::Copy {
+                start_block: 0,
+                block_count: 300,
+            },
+            Delta::Literal(b"some brand new bytes".to_vec()),
+            Delta::CopyRange {
+                offset: 70000,
+                len: 5,
+            },
+            // Positions past 4 GiB
+            Delta::Copy {
+                start_block: u32::MAX as u64 + 10,
+                block_count: 1,
+            },
+            Delta::CopyRange {
+                offset: 5 << 32,
+                len: 1 << 33,
+            },
+        ];
+        DeltaSet {
+            header: get_header(),
+            delta,
+            target_digest: vec![9; 32],
+        }
+    }
+
+    fn get_signatures(with_bytes: bool) -> SignatureSet {
+        let mut signatures = SignatureIndex::new();
+        // The last block sits past 4 GiB
+        for index in (0..20).chain([5 << 32]) {
+            let bytes = format!("block{}", index).into_bytes();
+            let sign = Signature {
+                index,
+                offset: index * 1024,
+                len: 1024,
+                checksum: vec![index as u8; 32],
+                bytes: with_bytes.then_some(bytes),
+            };
+            // A few of them share a weak hash
+            signatures.entry(index as u32 % 7).or_default().push(sign);
+        }
+        SignatureSet {
+            header: get_header(),
+            signatures,
+        }
+    }
+
+    fn assert_signatures_eq(expected: &SignatureSet, loaded: &SignatureSet) {
+        assert_eq!(expected.header, loaded.header);
+        assert_eq!(expected.signatures.len(), loaded.signatures.len());
+        for (weak_hash, signs) in &expected.signatures {
+            let loaded_signs = loaded.signatures.get(weak_hash).unwrap();
+            assert_eq!(signs.len(), loaded_signs.len());
+            for (sign, loaded_sign) in signs.iter().zip(loaded_signs) {
+                assert_eq!(sign.index, loaded_sign.index);
+                assert_eq!(sign.offset, loaded_sign.offset);
+                assert_eq!(sign.len, loaded_sign.len);
+                assert_eq!(sign.checksum, loaded_sign.checksum);
+                assert_eq!(sign.bytes, loaded_sign.bytes);
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::chunking::{check_chunk_size, Chunking, GearChunker};
+use crate::error::DiffError;
+use serde::{Deserialize, Serialize};
+
+/// Everything needed to use a signature or delta file without being told
+/// how it was made, along with a fingerprint of the original file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    /// Block size, or the average block size for content defined chunking
+    pub chunk_size: usize,
+    /// How the original file was cut into blocks
+    #[serde(default)]
+    pub chunking: Chunking,
+    /// Rolling hash used for the weak hashes
+    pub algorithm: String,
+    /// Hash used for the block checksums, with its length when cut short
+    pub strong_hash: String,
+    /// Length of the original (basis) file
+    pub basis_len: u64,
+    /// Blake2 digest of the whole original (basis) file
+    pub basis_digest: Vec<u8>,
+}
+
+impl Header {
+    /// Check the block sizes are ones signing could have used, so a damaged
+    /// or hand edited header fails to load instead of failing later on
+    pub fn validate(&self) -> Result<(), DiffError> {
+        check_chunk_size(self.chunk_size)
+            .and_then(|_| GearChunker::from_chunking(self.chunking, self.chunk_size))
+            .map(|_| ())
+            .map_err(|err| DiffError::Format(err.to_string()))
+    }
+
+    /// Index and length of the short last block, when the original file
+    /// doesn't end on a block boundary
+    pub fn tail_block(&self) -> Option<(u64, usize)> {
+        if self.chunk_size == 0 || self.chunking != Chunking::Fixed {
+            return None;
+        }
+        let chunk_size = self.chunk_size as u64;
+        match self.basis_len % chunk_size {
+            0 => None,
+            len => Some((self.basis_len / chunk_size, len as usize)),
+        }
+    }
+
+    /// Offset and length of a run of blocks in the original file. A run
+    /// that ends with the short last block only covers what's left of the file.
+    /// Runs too large to address are capped, copying them fails on the basis.
+    pub fn block_range(&self, start_block: u64, block_count: u64) -> (u64, u64) {
+        let chunk_size = self.chunk_size as u64;
+        let offset = start_block.saturating_mul(chunk_size);
+        let mut len = block_count.saturating_mul(chunk_size);
+        let end = offset.saturating_add(len);
+        if end > self.basis_len && end - self.basis_len < chunk_size {
+            len -= end - self.basis_len;
+        }
+        (offset, len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tail_block() {
+        let mut header = get_header(4, 10);
+        assert_eq!(header.tail_block(), Some((2, 2)));
+        header.basis_len = 12;
+        assert_eq!(header.tail_block(), None);
+        header.basis_len = 3;
+        assert_eq!(header.tail_block(), Some((0, 3)));
+        header.basis_len = 0;
+        assert_eq!(header.tail_block(), None);
+        // Content defined blocks have no fixed tail
+        header.basis_len = 10;
+        header.chunking = Chunking::gear(4);
+        assert_eq!(header.tail_block(), None);
+    }
+
+    #[test]
+    fn test_block_range() {
+        let header = get_header(4, 10);
+        assert_eq!(header.block_range(0, 2), (0, 8));
+        // The last block is only two bytes long
+        assert_eq!(header.block_range(1, 2), (4, 6));
+        assert_eq!(header.block_range(2, 1), (8, 2));
+        // Blocks past the end are left alone
+        assert_eq!(header.block_range(3, 1), (12, 4));
+
+        // Files past 4 GiB with more than u32::MAX blocks
+        let header = get_header(4, (20 << 30) + 2);
+        assert_eq!(header.tail_block(), Some((5 << 30, 2)));
+        assert_eq!(header.block_range(5 << 30, 1), (20 << 30, 2));
+        assert_eq!(header.block_range(1 << 32, 2), (16 << 30, 8));
+        // Runs too large to address don't wrap around
+        assert_eq!(header.block_range(u64::MAX, 2).0, u64::MAX);
+    }
+
+    fn get_header(chunk_size: usize, basis_len: u64) -> Header {
+        Header {
+            chunk_size,
+            chunking: Chunking::Fixed,
+            algorithm: "adler".to_string(),
+            strong_hash: "blake2s256".to_string(),
+            basis_len,
+            basis_digest: vec![0; 32],
+        }
+    }
+}
@@ -1,6 +1,9 @@
 pub mod algorithms;
+pub mod chunking;
 pub mod delta;
 pub mod error;
+pub mod format;
+pub mod header;
 pub mod patch;
 pub mod sign;
 pub mod utils;
\ No newline at end of file
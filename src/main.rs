@@ -1,16 +1,33 @@
 use clap::{Parser, Subcommand};
-use log::{error, info, warn};
+use log::{debug, error, info, warn, LevelFilter};
+use rustdiff::algorithms::registry::{self, RollingHashKind};
+use rustdiff::algorithms::strong_hash::{StrongHash, StrongHashKind};
+use rustdiff::chunking::{ChunkSize, Chunking};
 use rustdiff::delta::gen_delta_from_file;
-use rustdiff::error::DiffError;
+use rustdiff::error::{open_file, DiffError};
+use rustdiff::format::{load_signatures, save_signatures, Format};
 use rustdiff::patch::patch_file_with_delta;
-use rustdiff::sign::Signature;
+use rustdiff::sign::{CollisionReport, SignMode, Signature, SignatureSet};
 use simple_logger::SimpleLogger;
-use std::error::Error;
+use std::io::BufReader;
+use std::process::ExitCode;
 
-const DEFAULT_SIGN_FILE: &str = "data/output/signs.json";
-const DEFAULT_DELTA_FILE: &str = "data/output/delta.json";
+const DEFAULT_SIGN_FILE: &str = "data/output/signs.bin";
+const DEFAULT_DELTA_FILE: &str = "data/output/delta.bin";
 const DEFAULT_PATCH_FILE: &str = "data/output/patched.txt";
-const DEFAULT_CHUNK_SIZE: u8 = 4;
+const STDIN_PATH: &str = "-";
+
+/// How the blocks are cut and hashed, shared by sign and delta
+struct BlockArgs {
+    chunk_size: ChunkSize,
+    cdc: bool,
+    cdc_min: Option<usize>,
+    cdc_max: Option<usize>,
+    algorithm: String,
+    strong_hash: String,
+    jobs: usize,
+}
+
 #[derive(Parser)]
 struct Cli {
     #[command(subcommand)]
@@ -20,221 +37,524 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Sign {
+        /// File to sign, use - to read from stdin
         file: String,
-        chunk_size: Option<u8>,
+        /// Block size in bytes, or auto to pick one from the file length
+        chunk_size: Option<ChunkSize>,
         algorithm: Option<String>,
         output_path: Option<String>,
+        /// Embed the block bytes in the signatures
+        #[arg(long)]
+        full: bool,
+        /// Cut content defined blocks, chunk size is the average block size.
+        /// Blocks are kept between a quarter and four times of it by default.
+        #[arg(long)]
+        cdc: bool,
+        /// Shortest content defined block in bytes
+        #[arg(long, requires = "cdc")]
+        cdc_min: Option<usize>,
+        /// Longest content defined block in bytes
+        #[arg(long, requires = "cdc")]
+        cdc_max: Option<usize>,
+        /// Strong hash for the block checksums, like blake3 or blake3:len=8
+        #[arg(long)]
+        strong_hash: Option<String>,
+        /// Threads hashing the blocks, at most and by default the number of CPUs
+        #[arg(long)]
+        jobs: Option<usize>,
+        /// Write JSON instead of the binary format
+        #[arg(long)]
+        json: bool,
     },
     Delta {
-        file1: String,
-        file2: String,
-        chunk_size: Option<u8>,
+        /// Original file, use - to read from stdin
+        #[arg(required_unless_present = "signature")]
+        file1: Option<String>,
+        /// Modified file
+        #[arg(required_unless_present = "signature")]
+        file2: Option<String>,
+        /// Block size in bytes, or auto to pick one from the file length
+        chunk_size: Option<ChunkSize>,
         algorithm: Option<String>,
         output_path: Option<String>,
+        /// Signatures saved by sign, used in place of the original file.
+        /// The blocks are cut and hashed the way they were signed, and the
+        /// modified file and output are given with --modified and --output.
+        #[arg(
+            long,
+            requires = "modified",
+            conflicts_with_all = [
+                "file1", "file2", "chunk_size", "algorithm", "output_path",
+                "cdc", "strong_hash", "jobs"
+            ]
+        )]
+        signature: Option<String>,
+        /// Modified file, with --signature
+        #[arg(long, requires = "signature")]
+        modified: Option<String>,
+        /// Where to write the delta, with --signature
+        #[arg(long, requires = "signature")]
+        output: Option<String>,
+        /// Cut content defined blocks, chunk size is the average block size.
+        /// Blocks are kept between a quarter and four times of it by default.
+        #[arg(long)]
+        cdc: bool,
+        /// Shortest content defined block in bytes
+        #[arg(long, requires = "cdc")]
+        cdc_min: Option<usize>,
+        /// Longest content defined block in bytes
+        #[arg(long, requires = "cdc")]
+        cdc_max: Option<usize>,
+        /// Strong hash for the block checksums, like blake3 or blake3:len=8
+        #[arg(long)]
+        strong_hash: Option<String>,
+        /// Threads hashing the blocks, at most and by default the number of CPUs
+        #[arg(long)]
+        jobs: Option<usize>,
+        /// Write JSON instead of the binary format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Alias of delta --signature SIGNATURE --modified FILE --output OUTPUT_PATH
+    #[command(hide = true)]
+    DeltaSig {
+        /// Signatures of the original file
+        signature: String,
+        /// Modified file
+        file: String,
+        output_path: Option<String>,
+        /// Write JSON instead of the binary format
+        #[arg(long)]
+        json: bool,
     },
     Patch {
         file1: String,
         file2: String,
-        chunk_size: Option<u8>,
-        algorithm: Option<String>,
         output_path: Option<String>,
     },
+    /// List the available rolling hash algorithms
+    Algorithms,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    // Initialize logger
-    SimpleLogger::new().init().unwrap();
+fn main() -> ExitCode {
+    // Initialize logger, RUST_LOG=debug shows the details as well
+    SimpleLogger::new()
+        .with_level(LevelFilter::Info)
+        .env()
+        .init()
+        .unwrap();
 
     // Get the args
     let cli = Cli::parse();
 
-    match &cli.command {
+    match run(&cli.command) {
+        Ok(()) => {
+            info!("All done!");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            error!("{}, Exiting!", err);
+            ExitCode::from(exit_code(&err))
+        }
+    }
+}
+
+fn run(command: &Commands) -> Result<(), DiffError> {
+    match command {
         Commands::Sign {
             file,
             chunk_size,
             algorithm,
             output_path,
+            full,
+            cdc,
+            cdc_min,
+            cdc_max,
+            strong_hash,
+            jobs,
+            json,
         } => {
-            if !(std::path::Path::new(file).exists()) {
-                error!("File {} doesn't exist, Exiting!", file);
-                panic!();
-            }
+            check_input(file)?;
             info!("You requested to generate signature of the file {}", file);
             chunk_size_msg(chunk_size);
-            algo_msg(algorithm);
+            algo_msg(algorithm)?;
+            strong_hash_msg(strong_hash)?;
             path_msg(output_path);
-            gen_sign(file, chunk_size, algorithm, output_path)?;
+            let mode = match full {
+                true => SignMode::Full,
+                false => SignMode::Lean,
+            };
+            let block_args = verify_args(
+                chunk_size,
+                algorithm,
+                strong_hash,
+                cdc,
+                cdc_min,
+                cdc_max,
+                jobs,
+            );
+            gen_sign(file, &block_args, mode, get_format(json), output_path)?;
         }
         Commands::Delta {
-            file1,
-            file2,
+            file1: Some(file1),
+            file2: Some(file2),
             chunk_size,
             algorithm,
             output_path,
+            cdc,
+            cdc_min,
+            cdc_max,
+            strong_hash,
+            jobs,
+            json,
+            signature: None,
+            ..
         } => {
-            if !(std::path::Path::new(file1).exists()) {
-                error!("File {} doesn't exist, Exiting!", file1);
-                panic!();
-            }
-            if !(std::path::Path::new(file2).exists()) {
-                error!("File {} doesn't exist, Exiting!", file2);
-                panic!();
-            }
+            check_input(file1)?;
+            check_file(file2)?;
             info!(
                 "You requested to generate delta with files {} and {}",
                 file1, file2
             );
             chunk_size_msg(chunk_size);
-            algo_msg(algorithm);
+            algo_msg(algorithm)?;
+            strong_hash_msg(strong_hash)?;
             path_msg(output_path);
-            gen_delta(file1, file2, chunk_size, algorithm, output_path)?;
+            let block_args = verify_args(
+                chunk_size,
+                algorithm,
+                strong_hash,
+                cdc,
+                cdc_min,
+                cdc_max,
+                jobs,
+            );
+            gen_delta(file1, file2, &block_args, get_format(json), output_path)?;
+        }
+        Commands::Delta {
+            signature: Some(sig_file),
+            modified: Some(file),
+            output,
+            json,
+            ..
         }
+        | Commands::DeltaSig {
+            signature: sig_file,
+            file,
+            output_path: output,
+            json,
+        } => {
+            check_file(sig_file)?;
+            check_file(file)?;
+            info!(
+                "You requested to generate delta of the file {} with signatures {}",
+                file, sig_file
+            );
+            path_msg(output);
+            gen_delta_with_signatures(sig_file, file, get_format(json), output)?;
+        }
+        // Clap requires both files without --signature and --modified with it
+        Commands::Delta { .. } => unreachable!("delta arguments were not checked"),
         Commands::Patch {
             file1,
             file2,
-            chunk_size,
-            algorithm,
             output_path,
         } => {
-            if !(std::path::Path::new(file1).exists()) {
-                error!("File {} doesn't exist, Exiting!", file1);
-                panic!();
-            }
-            if !(std::path::Path::new(file2).exists()) {
-                error!("File {} doesn't exist, Exiting!", file2);
-                panic!();
-            }
+            check_file(file1)?;
+            check_file(file2)?;
             info!(
                 "You requested to patch original file {} with delta {}",
                 file1, file2
             );
-            chunk_size_msg(chunk_size);
-            algo_msg(algorithm);
             path_msg(output_path);
-            patch(file1, file2, chunk_size, algorithm, output_path)?;
+            patch(file1, file2, output_path)?;
+        }
+        Commands::Algorithms => {
+            for name in registry::available() {
+                println!("{}", name);
+            }
         }
     }
 
-    info!("All done!");
     Ok(())
 }
 
+/// Exit code for each kind of failure, so scripts can tell them apart
+fn exit_code(err: &DiffError) -> u8 {
+    match err {
+        DiffError::IO(_) => 1,
+        // Bad arguments, the same code clap exits with on a usage error
+        DiffError::UnknownAlgorithm(_)
+        | DiffError::AlgorithmExists(_)
+        | DiffError::AlgorithmParams(_)
+        | DiffError::UnknownStrongHash(_)
+        | DiffError::InvalidChunkSize(_)
+        | DiffError::Chunking(_) => 2,
+        DiffError::File { .. } => 3,
+        DiffError::SE(_)
+        | DiffError::Format(_)
+        | DiffError::Corrupt { .. }
+        | DiffError::Truncated { .. } => 4,
+        DiffError::BasisMismatch { .. } | DiffError::MissingBlock { .. } => 5,
+        DiffError::ChecksumMismatch { .. } => 6,
+    }
+}
+
 fn gen_sign(
-    file: &String,
-    chunk_size: &Option<u8>,
-    algorithm: &Option<String>,
+    file: &str,
+    block_args: &BlockArgs,
+    mode: SignMode,
+    format: Format,
     output_path: &Option<String>,
 ) -> Result<(), DiffError> {
-    // Verify the args
-    let (size, algo) = verify_args(chunk_size, algorithm);
     // Check if output path is provided
     let out_path = match output_path {
         Some(path) => path,
         None => DEFAULT_SIGN_FILE,
     };
     // Generate the signatures
-    let collisions = Signature::gen_sigs_save(file, size.into(), algo.as_str(), out_path)?;
-    match collisions > 0 {
-        true => warn!(
-            "{} collisions ocurred while generating signatures",
-            collisions
-        ),
-        false => (),
-    }
+    let (signatures, collisions) = sign_input(file, block_args, mode)?;
+    save_signatures(out_path, &signatures, format)?;
+    collision_msg(&collisions);
     info!("Output saved to {}", out_path);
     // All good
     Ok(())
 }
 
 fn gen_delta(
-    file1: &String,
-    file2: &String,
-    chunk_size: &Option<u8>,
-    algorithm: &Option<String>,
+    file1: &str,
+    file2: &str,
+    block_args: &BlockArgs,
+    format: Format,
     output_path: &Option<String>,
 ) -> Result<(), DiffError> {
-    // Verify the args
-    let (size, algo) = verify_args(chunk_size, algorithm);
     // Check if output path is provided
     let out_path = match output_path {
         Some(path) => path,
         None => DEFAULT_DELTA_FILE,
     };
     // Let's generate the signatures first
-    let (signatures, collisions) = Signature::gen_sigs(file1, size.into(), algo.as_str())?;
-    match collisions > 0 {
-        true => warn!(
-            "{} collisions ocurred while generating signatures",
-            collisions
-        ),
-        false => (),
-    }
+    let (signatures, collisions) = sign_input(file1, block_args, SignMode::Lean)?;
+    collision_msg(&collisions);
     // Generate the delta
-    gen_delta_from_file(file2, size.into(), algo.as_str(), out_path, signatures)?;
+    gen_delta_from_file(file2, out_path, format, signatures)?;
     info!("Output saved to {}", out_path);
     // All good
     Ok(())
 }
 
-fn patch(
-    file1: &String,
-    file2: &String,
-    chunk_size: &Option<u8>,
-    algorithm: &Option<String>,
+fn gen_delta_with_signatures(
+    sig_file: &str,
+    file: &str,
+    format: Format,
     output_path: &Option<String>,
 ) -> Result<(), DiffError> {
-    // Verify the args
-    let (size, algo) = verify_args(chunk_size, algorithm);
+    // Check if output path is provided
+    let out_path = match output_path {
+        Some(path) => path,
+        None => DEFAULT_DELTA_FILE,
+    };
+    // The signatures say how the blocks were cut and hashed
+    let signatures = load_signatures(sig_file)?;
+    info!(
+        "Signatures are for a {} byte file, chunk size {}, algorithm {}",
+        signatures.header.basis_len, signatures.header.chunk_size, signatures.header.algorithm
+    );
+    gen_delta_from_file(file, out_path, format, signatures)?;
+    info!("Output saved to {}", out_path);
+    // All good
+    Ok(())
+}
+
+fn patch(file1: &str, file2: &str, output_path: &Option<String>) -> Result<(), DiffError> {
     // Check if output path is provided
     let out_path = match output_path {
         Some(path) => path,
         None => DEFAULT_PATCH_FILE,
     };
-    // Let's generate the signatures first
-    let (signatures, collisions) = Signature::gen_sigs(file1, size.into(), algo.as_str())?;
-    match collisions > 0 {
-        true => warn!(
-            "{} collisions ocurred while generating signatures",
-            collisions
-        ),
-        false => (),
-    }
-    // Patch the file
-    patch_file_with_delta(file2.to_string(), out_path.to_string(), signatures)?;
+    // Patch the file, chunk size comes from the delta header
+    // and blocks are read straight from the original file
+    patch_file_with_delta(file1.to_string(), file2.to_string(), out_path.to_string())?;
     info!("Output saved to {}", out_path);
     // All good
     Ok(())
 }
 
-fn verify_args(chunk_size: &Option<u8>, algorithm: &Option<String>) -> (u8, String) {
-    // Check if chunk size is provided, otherwise use default
-    let size = match chunk_size {
-        Some(s) => s,
-        None => &DEFAULT_CHUNK_SIZE,
+/// Sign the file, or stdin when the path is -
+fn sign_input(
+    file: &str,
+    block_args: &BlockArgs,
+    mode: SignMode,
+) -> Result<(SignatureSet, CollisionReport), DiffError> {
+    let BlockArgs {
+        chunk_size,
+        cdc,
+        cdc_min,
+        cdc_max,
+        algorithm,
+        strong_hash,
+        jobs,
+    } = block_args;
+    // The length of stdin isn't known until it's been read
+    let basis_len = match file {
+        STDIN_PATH => None,
+        _ => {
+            let metadata = std::fs::metadata(file).map_err(|err| DiffError::file(file, err))?;
+            Some(metadata.len())
+        }
     };
+    let chunk_size = chunk_size.resolve(basis_len)?;
+    if block_args.chunk_size == ChunkSize::Auto {
+        info!("Picked chunk size {}", chunk_size);
+    }
+    // Bounds that aren't given follow the average
+    let chunking = match (cdc, Chunking::gear(chunk_size)) {
+        (true, Chunking::Gear { min, max }) => Chunking::Gear {
+            min: cdc_min.unwrap_or(min),
+            max: cdc_max.unwrap_or(max),
+        },
+        _ => Chunking::Fixed,
+    };
+
+    match file {
+        STDIN_PATH => {
+            let stdin = std::io::stdin();
+            Signature::gen_sigs_parallel(
+                stdin.lock(),
+                chunk_size,
+                chunking,
+                algorithm,
+                strong_hash,
+                mode,
+                *jobs,
+            )
+        }
+        _ => {
+            let reader = BufReader::new(open_file(file)?);
+            Signature::gen_sigs_parallel(
+                reader,
+                chunk_size,
+                chunking,
+                algorithm,
+                strong_hash,
+                mode,
+                *jobs,
+            )
+        }
+    }
+}
+
+/// Fail before any output is written when an input file can't be found
+fn check_file(file: &str) -> Result<(), DiffError> {
+    std::fs::metadata(file)
+        .map(|_| ())
+        .map_err(|err| DiffError::file(file, err))
+}
+
+/// Same as `check_file`, stdin is always there
+fn check_input(file: &str) -> Result<(), DiffError> {
+    match file {
+        STDIN_PATH => Ok(()),
+        _ => check_file(file),
+    }
+}
+
+fn verify_args(
+    chunk_size: &Option<ChunkSize>,
+    algorithm: &Option<String>,
+    strong_hash: &Option<String>,
+    cdc: &bool,
+    cdc_min: &Option<usize>,
+    cdc_max: &Option<usize>,
+    jobs: &Option<usize>,
+) -> BlockArgs {
+    // Check if chunk size is provided, otherwise pick one from the file
+    let size = chunk_size.unwrap_or_default();
 
     // Check if algorithm provided, otherwise use default
     let algo = match algorithm {
         Some(al) => al,
-        None => "adler",
+        None => RollingHashKind::Adler32.name(),
+    };
+
+    let strong_hash = match strong_hash {
+        Some(spec) => spec,
+        None => StrongHashKind::Blake2s.name(),
     };
 
-    (*size, algo.to_string())
+    // Check if jobs provided, otherwise use every core
+    let jobs =
+        jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |cores| cores.get()));
+
+    BlockArgs {
+        chunk_size: size,
+        cdc: *cdc,
+        cdc_min: *cdc_min,
+        cdc_max: *cdc_max,
+        algorithm: algo.to_string(),
+        strong_hash: strong_hash.to_string(),
+        jobs,
+    }
 }
 
-fn chunk_size_msg(chunk_size: &Option<u8>) {
+fn get_format(json: &bool) -> Format {
+    match json {
+        true => Format::Json,
+        false => Format::Binary,
+    }
+}
+
+fn chunk_size_msg(chunk_size: &Option<ChunkSize>) {
     if let Some(size) = chunk_size {
         info!("Using chunk size {}", size);
     }
 }
 
-fn algo_msg(algorithm: &Option<String>) {
+fn strong_hash_msg(strong_hash: &Option<String>) -> Result<(), DiffError> {
+    if let Some(spec) = strong_hash {
+        if let Err(err) = StrongHash::from_spec(spec) {
+            let names: Vec<&str> = StrongHashKind::ALL.iter().map(|kind| kind.name()).collect();
+            error!(
+                "Not a valid strong hash, use one of {} with an optional :len=N",
+                names.join(", ")
+            );
+            return Err(err);
+        }
+        info!("Using strong hash {}", spec);
+    }
+    Ok(())
+}
+
+fn algo_msg(algorithm: &Option<String>) -> Result<(), DiffError> {
     if let Some(algo) = algorithm {
-        if !(algo.as_str() == "adler" || algo.as_str() == "fletcher") {
-            panic!("Not a valid algorithm value, use either 'adler' or 'fletcher'");
+        if let Err(err) = registry::create(algo) {
+            error!(
+                "Not a valid algorithm value, use one of: {}",
+                registry::available().join(", ")
+            );
+            return Err(err);
         }
         info!("Using algorithm {}", algo);
     }
+    Ok(())
+}
+
+/// One warning for all the collisions, the blocks involved are only logged
+/// at debug level since there can be a lot of them
+fn collision_msg(collisions: &CollisionReport) {
+    if !collisions.is_empty() {
+        warn!(
+            "{} blocks share {} weak hashes with another block",
+            collisions.colliding_blocks(),
+            collisions.collisions.len()
+        );
+        for collision in &collisions.collisions {
+            debug!(
+                "Blocks {:?} share the weak hash {}",
+                collision.indices, collision.weak_hash
+            );
+        }
+    }
 }
 
 fn path_msg(output_path: &Option<String>) {
@@ -1,60 +1,190 @@
-use crate::delta::Delta;
-use crate::error::DiffError;
-use crate::sign::Signature;
-use log::error;
-use serde_json;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufReader, Write};
+use crate::delta::{Delta, DeltaSet};
+use crate::error::{open_file, DiffError};
+use crate::format::DeltaReader;
+use crate::header::Header;
+use crate::utils::{get_blake2_from_reader, Blake2Writer};
+use std::fs::{remove_file, rename, File};
+use std::io::{BufReader, BufWriter, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
 
+/// Rebuild the modified file from the original (basis) file and the delta.
+/// Matched blocks are read straight out of the basis file, so the
+/// signatures are not needed on this side. The chunk size comes from the
+/// delta header, which also has to agree with the basis file.
+///
+/// The output goes to a uniquely named temporary file next to `out_file` and
+/// is only moved into place once it matches the digest of the modified file.
 pub fn patch_file_with_delta(
+    basis_file: String,
     delta_file: String,
     out_file: String,
-    signatures: HashMap<u32, Signature>,
 ) -> Result<(), DiffError> {
-    let f = File::open(delta_file)?;
-    let reader = BufReader::new(f);
-    let loaded_delta: Vec<Delta> = serde_json::from_reader(reader)?;
+    let delta = BufReader::new(open_file(&delta_file)?);
+    let basis = open_file(&basis_file)?;
 
-    let mut output = Vec::<u8>::new();
+    let (tmp_file, f) = create_tmp_file(&out_file)?;
+    let result = {
+        let mut output = BufWriter::new(f);
+        patch_from_reader(basis, delta, &mut output).and_then(|_| Ok(output.flush()?))
+    }
+    .and_then(|_| rename(&tmp_file, &out_file).map_err(|err| DiffError::file(&out_file, err)));
+    if let Err(err) = result {
+        let _ = remove_file(&tmp_file);
+        return Err(err.with_paths(&basis_file, &out_file));
+    }
 
-    for delta in loaded_delta {
-        match delta {
-            Delta::B(b) => {
-                output.push(b);
-            }
-            Delta::I(i) => {
-                if let Some(mut data) = get_data(i, &signatures) {
-                    output.append(&mut data);
-                } else {
-                    error!("Couldn't find the indexed data while patching file!, Exiting");
-                    panic!();
-                }
-            }
+    Ok(())
+}
+
+/// Create a new file next to `out_file` for the output to be written to.
+/// The name never matches an existing file, so nothing the user has is
+/// overwritten and patches to the same output don't share it.
+fn create_tmp_file(out_file: &str) -> Result<(String, File), DiffError> {
+    loop {
+        let tmp_file = format!(
+            "{}.{}-{:08x}.tmp",
+            out_file,
+            std::process::id(),
+            rand::random::<u32>()
+        );
+        match File::options().write(true).create_new(true).open(&tmp_file) {
+            Ok(file) => return Ok((tmp_file, file)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(DiffError::file(&tmp_file, err)),
         }
     }
+}
+
+/// Rebuild the modified data from a seekable basis and a delta stream. The
+/// instructions are applied one at a time as they are read, block ranges
+/// are copied from the basis straight into the output. Fails with a
+/// checksum mismatch once the output is written if it doesn't match the
+/// digest in the delta.
+pub fn patch_from_reader<B: Read + Seek, D: Read, W: Write>(
+    mut basis: B,
+    delta: D,
+    output: W,
+) -> Result<(), DiffError> {
+    let mut delta_reader = DeltaReader::new(delta)?;
+    verify_basis(&mut basis, delta_reader.header())?;
 
-    // Write data to output file
-    let mut of = File::create(out_file)?;
-    of.write_all(&output)?;
+    let header = delta_reader.header().clone();
+    let mut output = Blake2Writer::new(output);
+    while let Some(op) = delta_reader.next_op()? {
+        apply_op(&mut basis, &header, &op, &mut output)?;
+    }
 
+    let (_, target_digest) = delta_reader.finish()?;
+    let mut writer = check_output(output, &target_digest)?;
+    writer.flush()?;
     Ok(())
 }
 
-fn get_data(i: u32, signatures: &HashMap<u32, Signature>) -> Option<Vec<u8>> {
-    signatures.iter().find_map(|(_, val)| {
-        if val.index == i {
-            Some(val.bytes.clone())
-        } else {
-            None
+/// Rebuild the modified data from an original and a delta that are
+/// already in memory
+pub fn patch_from_bytes(basis: &[u8], delta: &DeltaSet) -> Result<Vec<u8>, DiffError> {
+    let mut basis = Cursor::new(basis);
+    verify_basis(&mut basis, &delta.header)?;
+
+    let mut output = Blake2Writer::new(Vec::new());
+    for op in &delta.delta {
+        apply_op(&mut basis, &delta.header, op, &mut output)?;
+    }
+    check_output(output, &delta.target_digest)
+}
+
+/// Write out what a single delta instruction stands for
+fn apply_op<B: Read + Seek, W: Write>(
+    basis: &mut B,
+    header: &Header,
+    op: &Delta,
+    output: &mut W,
+) -> Result<(), DiffError> {
+    match op {
+        Delta::Literal(bytes) => {
+            output.write_all(bytes)?;
+        }
+        Delta::Copy {
+            start_block,
+            block_count,
+        } => {
+            let (offset, len) = header.block_range(*start_block, *block_count);
+            check_range(header, offset, len)?;
+            copy_range(basis, offset, len, output)?;
+        }
+        Delta::CopyRange { offset, len } => {
+            check_range(header, *offset, *len)?;
+            copy_range(basis, *offset, *len, output)?;
         }
-    })
+    }
+    Ok(())
+}
+
+/// Hand back the output once it's known to match the modified file
+fn check_output<W: Write>(output: Blake2Writer<W>, target_digest: &[u8]) -> Result<W, DiffError> {
+    let (writer, digest) = output.finalize();
+    if digest != target_digest {
+        return Err(DiffError::ChecksumMismatch {
+            path: None,
+            reason: "patched output doesn't match the modified file".to_string(),
+        });
+    }
+    Ok(writer)
+}
+
+/// Make sure the basis file is the one the delta was generated against
+fn verify_basis<B: Read + Seek>(basis: &mut B, header: &Header) -> Result<(), DiffError> {
+    basis.seek(SeekFrom::Start(0))?;
+    let (digest, len) = get_blake2_from_reader(&mut *basis)?;
+    if len != header.basis_len {
+        return Err(DiffError::BasisMismatch {
+            path: None,
+            reason: format!("expected {} bytes, found {}", header.basis_len, len),
+        });
+    }
+    if digest != header.basis_digest {
+        return Err(DiffError::BasisMismatch {
+            path: None,
+            reason: "contents differ from the signed file".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Make sure a copy stays within the basis file, before any of it is written
+fn check_range(header: &Header, offset: u64, len: u64) -> Result<(), DiffError> {
+    match offset.checked_add(len) {
+        Some(end) if end <= header.basis_len => Ok(()),
+        _ => Err(DiffError::MissingBlock {
+            offset,
+            len,
+            basis_len: header.basis_len,
+        }),
+    }
+}
+
+/// Copy a range of bytes out of the basis file into the output
+fn copy_range<B: Read + Seek, W: Write>(
+    basis: &mut B,
+    offset: u64,
+    len: u64,
+    output: &mut W,
+) -> Result<(), DiffError> {
+    basis.seek(SeekFrom::Start(offset))?;
+    let copied = std::io::copy(&mut basis.by_ref().take(len), output)?;
+    if copied != len {
+        return Err(DiffError::IO(std::io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::delta::gen_delta_from_file;
+    use crate::chunking::Chunking;
+    use crate::delta::{gen_delta, gen_delta_from_bytes, gen_delta_from_file};
+    use crate::format::{load_delta, save_delta, write_delta, Format};
+    use crate::sign::{SignMode, Signature};
+    use crate::utils::get_blake2;
     use rand::{thread_rng, Rng};
 
     use std::fs::{read_to_string, remove_file, write, File};
@@ -93,31 +223,194 @@ mod test {
     }
 
     #[test]
-    fn test_get_data_adler_size4() {
+    fn test_copy_range_adler_size4() {
         let chunk_size = 4;
         let algorithm = "adler";
-        test_get_data(chunk_size, algorithm);
+        test_copy_range(chunk_size, algorithm);
     }
 
     #[test]
-    fn test_get_data_fletcher_size4() {
+    fn test_copy_range_fletcher_size4() {
         let chunk_size = 4;
         let algorithm = "fletcher";
-        test_get_data(chunk_size, algorithm);
+        test_copy_range(chunk_size, algorithm);
     }
 
     #[test]
-    fn test_get_data_adler_size12() {
+    fn test_copy_range_adler_size12() {
         let chunk_size = 12;
         let algorithm = "adler";
-        test_get_data(chunk_size, algorithm);
+        test_copy_range(chunk_size, algorithm);
     }
 
     #[test]
-    fn test_get_data_fletcher_size12() {
+    fn test_copy_range_fletcher_size12() {
         let chunk_size = 12;
         let algorithm = "fletcher";
-        test_get_data(chunk_size, algorithm);
+        test_copy_range(chunk_size, algorithm);
+    }
+
+    #[test]
+    fn test_copy_range_sparse() {
+        // A sparse file larger than 4 GiB, only the bytes written take up space
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        let basis_len: u64 = 6 << 30;
+        let far: u64 = 5 << 30;
+        let mut basis = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_in_file)
+            .unwrap();
+        basis.set_len(basis_len).unwrap();
+        basis.seek(SeekFrom::Start(far)).unwrap();
+        basis.write_all(b"far away").unwrap();
+
+        // One byte blocks, so the block index is past u32::MAX as well
+        let header = Header {
+            chunk_size: 1,
+            chunking: Chunking::Fixed,
+            algorithm: "adler".to_string(),
+            strong_hash: "blake2s256".to_string(),
+            basis_len,
+            basis_digest: vec![0; 32],
+        };
+        let (offset, len) = header.block_range(far, 8);
+        assert_eq!((offset, len), (far, 8));
+        let mut output = Vec::new();
+        copy_range(&mut basis, offset, len, &mut output).unwrap();
+        assert_eq!(output, b"far away");
+
+        // The hole reads back as zeros, and the end of the file still ends it
+        let mut output = Vec::new();
+        copy_range(&mut basis, basis_len - 4, 4, &mut output).unwrap();
+        assert_eq!(output, vec![0; 4]);
+        assert!(copy_range(&mut basis, basis_len - 4, 8, &mut Vec::new()).is_err());
+        remove_file(tmp_in_file).unwrap();
+    }
+
+    #[test]
+    fn test_patch_from_reader() {
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        let data = "Nothing but a copy buffer is needed to patch a file";
+        write(&tmp_in_file, data).unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, 5, "adler", SignMode::Lean).unwrap();
+
+        let modified_data = "Nothing but a small copy buffer is ever needed to patch a file";
+        let mut delta = Vec::new();
+        gen_delta(modified_data.as_bytes(), &mut delta, &signatures).unwrap();
+
+        // Everything stays in memory, the basis only has to be seekable
+        let mut output = Vec::new();
+        let basis = std::io::Cursor::new(data.as_bytes());
+        patch_from_reader(basis, delta.as_slice(), &mut output).unwrap();
+        assert_eq!(output, modified_data.as_bytes());
+
+        // A truncated delta is an error
+        let mut output = Vec::new();
+        let basis = std::io::Cursor::new(data.as_bytes());
+        let truncated = &delta[..delta.len() - 4];
+        assert!(patch_from_reader(basis, truncated, &mut output).is_err());
+
+        remove_file(tmp_in_file).unwrap();
+    }
+
+    #[test]
+    fn test_patch_from_bytes() {
+        let data = b"Blobs kept in memory never have to touch the disk";
+        let modified = b"Blobs kept in memory don't have to touch the disk at all";
+        let (signatures, _) =
+            Signature::gen_sigs_from_bytes(data, 4, "fletcher", SignMode::Lean).unwrap();
+        let delta = gen_delta_from_bytes(modified, &signatures).unwrap();
+        assert_eq!(patch_from_bytes(data, &delta).unwrap(), modified);
+
+        // The wrong original or a tampered delta are both caught
+        let result = patch_from_bytes(modified, &delta);
+        assert!(matches!(
+            result,
+            Err(DiffError::BasisMismatch { path: None, .. })
+        ));
+        let mut tampered = delta.clone();
+        tampered.delta.push(Delta::Literal(b"!".to_vec()));
+        let result = patch_from_bytes(data, &tampered);
+        assert!(matches!(result, Err(DiffError::ChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn test_patch_blocks_out_of_order() {
+        // Every block is located from its index alone, in any order
+        let block_count: u64 = 4096;
+        let data: Vec<u8> = (0..block_count * 4).map(|n| (n / 4 % 251) as u8).collect();
+        let delta: Vec<Delta> = (0..block_count)
+            .rev()
+            .map(|start_block| Delta::Copy {
+                start_block,
+                block_count: 1,
+            })
+            .collect();
+        let modified_data: Vec<u8> = data.chunks(4).rev().flatten().copied().collect();
+        let delta_set = DeltaSet {
+            header: Header {
+                chunk_size: 4,
+                chunking: Chunking::Fixed,
+                algorithm: "adler".to_string(),
+                strong_hash: "blake2s256".to_string(),
+                basis_len: data.len() as u64,
+                basis_digest: get_blake2(data.clone()).unwrap(),
+            },
+            delta,
+            target_digest: get_blake2(modified_data.clone()).unwrap(),
+        };
+        let mut delta = Vec::new();
+        write_delta(&mut delta, &delta_set, Format::Binary).unwrap();
+
+        let mut output = Vec::new();
+        let basis = std::io::Cursor::new(&data);
+        patch_from_reader(basis, delta.as_slice(), &mut output).unwrap();
+        assert_eq!(output, modified_data);
+    }
+
+    #[test]
+    fn test_patch_missing_block() {
+        let data = b"ten bytes!".to_vec();
+        let header = Header {
+            chunk_size: 4,
+            chunking: Chunking::Fixed,
+            algorithm: "adler".to_string(),
+            strong_hash: "blake2s256".to_string(),
+            basis_len: data.len() as u64,
+            basis_digest: get_blake2(data.clone()).unwrap(),
+        };
+        let ops = [
+            Delta::Copy {
+                start_block: 3,
+                block_count: 1,
+            },
+            Delta::CopyRange { offset: 8, len: 3 },
+            Delta::CopyRange {
+                offset: u64::MAX,
+                len: 2,
+            },
+        ];
+        for op in ops {
+            let delta_set = DeltaSet {
+                header: header.clone(),
+                delta: vec![Delta::Literal(b"before".to_vec()), op],
+                target_digest: vec![0; 32],
+            };
+            let mut delta = Vec::new();
+            write_delta(&mut delta, &delta_set, Format::Binary).unwrap();
+
+            let mut output = Vec::new();
+            let basis = std::io::Cursor::new(&data);
+            let result = patch_from_reader(basis, delta.as_slice(), &mut output);
+            assert!(matches!(
+                result,
+                Err(DiffError::MissingBlock { basis_len: 10, .. })
+            ));
+        }
     }
 
     fn test_patch_file_with_delta(chunk_size: usize, algorithm: &str) {
@@ -139,22 +432,202 @@ mod test {
         write(&tmp_m_in_file, modified_data).unwrap();
 
         // Generate the signatures and delta
-        let (signatures, _) = Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm).unwrap();
-        gen_delta_from_file(
-            &tmp_m_in_file,
-            chunk_size,
-            algorithm,
-            &tmp_delta_file,
-            signatures.clone(),
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Lean).unwrap();
+        gen_delta_from_file(&tmp_m_in_file, &tmp_delta_file, Format::Binary, signatures).unwrap();
+
+        // Patch the file
+        patch_file_with_delta(
+            tmp_in_file.clone(),
+            tmp_delta_file.clone(),
+            tmp_out_file.clone(),
         )
         .unwrap();
 
+        // Verify the results
+        let data = read_to_string(tmp_out_file.clone()).unwrap();
+        assert_eq!(data, modified_data);
+
+        // Cleanup
+        remove_file(tmp_in_file).unwrap();
+        remove_file(tmp_m_in_file).unwrap();
+        remove_file(tmp_delta_file).unwrap();
+        remove_file(tmp_out_file).unwrap();
+    }
+
+    #[test]
+    fn test_patch_file_with_copy_range() {
+        // Create the test files
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        let tmp_delta_file = format!("{}_{}", TEST_DELTA_FILE, get_rnum());
+        let tmp_out_file = format!("{}_{}", TEST_OUT_FILE, get_rnum());
+        write(&tmp_in_file, "0123456789abcdef").unwrap();
+
+        // Mix every kind of instruction
+        let (basis_digest, basis_len) =
+            get_blake2_from_reader(File::open(&tmp_in_file).unwrap()).unwrap();
+        let header = Header {
+            chunk_size: 4,
+            chunking: Chunking::Fixed,
+            algorithm: "adler".to_string(),
+            strong_hash: "blake2s256".to_string(),
+            basis_len,
+            basis_digest,
+        };
+        let delta = vec![
+            Delta::CopyRange { offset: 10, len: 3 },
+            Delta::Literal(b"-".to_vec()),
+            Delta::Copy {
+                start_block: 1,
+                block_count: 2,
+            },
+            Delta::CopyRange { offset: 1, len: 1 },
+        ];
+        let delta_set = DeltaSet {
+            header,
+            delta,
+            target_digest: get_blake2(b"abc-456789ab1".to_vec()).unwrap(),
+        };
+        write(&tmp_delta_file, serde_json::to_string(&delta_set).unwrap()).unwrap();
+
         // Patch the file
-        patch_file_with_delta(tmp_delta_file.clone(), tmp_out_file.clone(), signatures).unwrap();
+        patch_file_with_delta(
+            tmp_in_file.clone(),
+            tmp_delta_file.clone(),
+            tmp_out_file.clone(),
+        )
+        .unwrap();
 
         // Verify the results
         let data = read_to_string(tmp_out_file.clone()).unwrap();
-        assert_eq!(data, modified_data);
+        assert_eq!(data, "abc-456789ab1");
+
+        // Cleanup
+        remove_file(tmp_in_file).unwrap();
+        remove_file(tmp_delta_file).unwrap();
+        remove_file(tmp_out_file).unwrap();
+    }
+
+    #[test]
+    fn test_patch_file_wrong_basis() {
+        let chunk_size = 4;
+        let algorithm = "adler";
+        // Create the test files
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        let tmp_m_in_file = format!("{}_{}", TEST_M_IN_FILE, get_rnum());
+        let tmp_delta_file = format!("{}_{}", TEST_DELTA_FILE, get_rnum());
+        let tmp_out_file = format!("{}_{}", TEST_OUT_FILE, get_rnum());
+        write(&tmp_in_file, "Every block here is going to be copied").unwrap();
+        write(&tmp_m_in_file, "Every block here is going to be copied!").unwrap();
+
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Lean).unwrap();
+        gen_delta_from_file(&tmp_m_in_file, &tmp_delta_file, Format::Binary, signatures).unwrap();
+
+        // Same length, different contents
+        write(&tmp_in_file, "Every block here is going to be COPIED").unwrap();
+        let result = patch_file_with_delta(
+            tmp_in_file.clone(),
+            tmp_delta_file.clone(),
+            tmp_out_file.clone(),
+        );
+        assert!(matches!(result, Err(DiffError::BasisMismatch { .. })));
+
+        // Different length, the error names the basis file
+        write(&tmp_in_file, "Every block").unwrap();
+        let result = patch_file_with_delta(
+            tmp_in_file.clone(),
+            tmp_delta_file.clone(),
+            tmp_out_file.clone(),
+        );
+        assert!(matches!(
+            &result,
+            Err(DiffError::BasisMismatch { path: Some(path), .. }) if *path == tmp_in_file
+        ));
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            format!(
+                "{}: basis file mismatch: expected 38 bytes, found 11",
+                tmp_in_file
+            )
+        );
+
+        // Cleanup
+        remove_file(tmp_in_file).unwrap();
+        remove_file(tmp_m_in_file).unwrap();
+        remove_file(tmp_delta_file).unwrap();
+    }
+
+    #[test]
+    fn test_patch_file_bad_output() {
+        let chunk_size = 4;
+        let algorithm = "fletcher";
+        // Create the test files
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        let tmp_m_in_file = format!("{}_{}", TEST_M_IN_FILE, get_rnum());
+        let tmp_delta_file = format!("{}_{}", TEST_DELTA_FILE, get_rnum());
+        let tmp_out_file = format!("{}_{}", TEST_OUT_FILE, get_rnum());
+        write(&tmp_in_file, "The output is checked before it is kept").unwrap();
+        write(
+            &tmp_m_in_file,
+            "The output is always checked before it is kept",
+        )
+        .unwrap();
+        write(&tmp_out_file, "Previous output").unwrap();
+
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Lean).unwrap();
+        gen_delta_from_file(&tmp_m_in_file, &tmp_delta_file, Format::Binary, signatures).unwrap();
+        let mut delta_set = load_delta(&tmp_delta_file).unwrap();
+        let original_delta = delta_set.clone();
+        let user_tmp_file = format!("{}.tmp", tmp_out_file);
+        write(&user_tmp_file, "Not a temporary file").unwrap();
+        assert_eq!(
+            delta_set.target_digest,
+            get_blake2(read_to_string(&tmp_m_in_file).unwrap().into_bytes()).unwrap()
+        );
+
+        // Tamper with one of the literal runs
+        for delta in delta_set.delta.iter_mut() {
+            if let Delta::Literal(bytes) = delta {
+                bytes[0] ^= 0xff;
+            }
+        }
+        save_delta(&tmp_delta_file, &delta_set, Format::Binary).unwrap();
+        let result = patch_file_with_delta(
+            tmp_in_file.clone(),
+            tmp_delta_file.clone(),
+            tmp_out_file.clone(),
+        );
+        assert!(matches!(
+            result,
+            Err(DiffError::ChecksumMismatch { path: Some(path), .. }) if path == tmp_out_file
+        ));
+
+        // The previous output and a file that happens to share the old
+        // temporary name are left alone, and the temporary file is gone
+        let data = read_to_string(&tmp_out_file).unwrap();
+        assert_eq!(data, "Previous output");
+        assert_eq!(
+            read_to_string(&user_tmp_file).unwrap(),
+            "Not a temporary file"
+        );
+        assert_eq!(tmp_files(&tmp_out_file), vec![user_tmp_file.as_str()]);
+
+        // A good patch only leaves the output behind
+        save_delta(&tmp_delta_file, &original_delta, Format::Binary).unwrap();
+        patch_file_with_delta(
+            tmp_in_file.clone(),
+            tmp_delta_file.clone(),
+            tmp_out_file.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            read_to_string(&tmp_out_file).unwrap(),
+            read_to_string(&tmp_m_in_file).unwrap()
+        );
+        assert_eq!(tmp_files(&tmp_out_file), vec![user_tmp_file.as_str()]);
+        remove_file(user_tmp_file).unwrap();
 
         // Cleanup
         remove_file(tmp_in_file).unwrap();
@@ -163,7 +636,7 @@ mod test {
         remove_file(tmp_out_file).unwrap();
     }
 
-    fn test_get_data(chunk_size: usize, algorithm: &str) {
+    fn test_copy_range(chunk_size: usize, algorithm: &str) {
         // Create the test files
         let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
         File::create(tmp_in_file.as_str()).unwrap();
@@ -173,19 +646,43 @@ mod test {
         write(&tmp_in_file, data).unwrap();
 
         // Generate the signatures
-        let (signatures, _) = Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm).unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Full).unwrap();
 
-        // Verify data
-        for (_, sign) in &signatures {
-            let index = sign.index;
-            let test_data = get_data(index, &signatures).unwrap();
-            assert_eq!(test_data, sign.bytes);
+        // Blocks read from the basis should match the embedded bytes
+        let mut basis = File::open(&tmp_in_file).unwrap();
+        for sign in signatures.signatures.values().flatten() {
+            // The last block may be short
+            let (offset, len) = signatures.header.block_range(sign.index, 1);
+            let mut test_data = Vec::new();
+            copy_range(&mut basis, offset, len, &mut test_data).unwrap();
+            assert_eq!(Some(test_data), sign.bytes);
         }
+        // Ranges don't have to line up with the blocks
+        let mut test_data = Vec::new();
+        copy_range(&mut basis, 5, 9, &mut test_data).unwrap();
+        assert_eq!(test_data, &data.as_bytes()[5..14]);
+
+        // Ranges past the end of the basis are an error
+        let mut test_data = Vec::new();
+        assert!(copy_range(&mut basis, data.len() as u64 - 2, 4, &mut test_data).is_err());
 
         // Cleanup
         remove_file(tmp_in_file).unwrap();
     }
 
+    /// Files next to the output whose names start with the output's
+    fn tmp_files(out_file: &str) -> Vec<String> {
+        let path = std::path::Path::new(out_file);
+        let prefix = format!("{}.", path.file_name().unwrap().to_string_lossy());
+        std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(&prefix))
+            .map(|name| path.with_file_name(name).to_string_lossy().into_owned())
+            .collect()
+    }
+
     fn get_rnum() -> u32 {
         let mut rng = thread_rng();
         rng.gen()
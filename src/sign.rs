@@ -1,21 +1,96 @@
-use crate::algorithms::adler_32::Adler32;
 use crate::algorithms::algorithm::Algorithm;
-use crate::algorithms::fletcher_32::Fletcher32;
-use crate::error::DiffError;
-use crate::utils::get_blake2;
-use log::warn;
+use crate::algorithms::registry;
+use crate::algorithms::strong_hash::{StrongHash, StrongHashKind};
+use crate::chunking::{check_chunk_size, for_each_fixed_block, Chunking, GearChunker};
+use crate::error::{open_file, DiffError};
+use crate::format::{save_signatures, Format};
+use crate::header::Header;
+use crate::utils::Blake2Reader;
+use log::debug;
 use serde::{Deserialize, Serialize};
-use serde_json;
 use std::collections::HashMap;
 use std::fmt;
-use std::fs::File;
-use std::io::Read;
+use std::io::{BufReader, Read};
+
+/// Blocks and bytes every thread hashes at a time when signing in parallel
+const BATCH_BLOCKS: usize = 1024;
+const BATCH_BYTES: usize = 4 * 1024 * 1024;
+
+/// Whether the signatures should carry a copy of the block they describe
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignMode {
+    /// Keep the block bytes next to the hashes, useful for debugging
+    Full,
+    /// Only keep the index, weak hash and strong hash of each block
+    Lean,
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Signature {
-    pub index: u32,
+    pub index: u64,
+    /// Where the block starts in the original file
+    #[serde(default)]
+    pub offset: u64,
+    /// Length of the block, only the last fixed block can be short
+    #[serde(default)]
+    pub len: u64,
     pub checksum: Vec<u8>,
-    pub bytes: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// Signatures keyed by their weak hash. Every block that shares a weak hash
+/// is kept, the strong checksum tells them apart.
+pub type SignatureIndex = HashMap<u32, Vec<Signature>>;
+
+/// Signatures of a file along with the header describing how they were made
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignatureSet {
+    pub header: Header,
+    pub signatures: SignatureIndex,
+}
+
+/// Blocks with different contents that ended up with the same weak hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    pub weak_hash: u32,
+    pub indices: Vec<u64>,
+}
+
+/// Every weak hash collision found while generating signatures
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CollisionReport {
+    pub collisions: Vec<Collision>,
+}
+
+impl CollisionReport {
+    /// Collect the weak hashes that are shared by more than one block
+    pub fn from_signatures(signatures: &SignatureIndex) -> Self {
+        let mut collisions: Vec<Collision> = signatures
+            .iter()
+            .filter(|(_, signs)| signs.len() > 1)
+            .map(|(weak_hash, signs)| Collision {
+                weak_hash: *weak_hash,
+                indices: signs.iter().map(|sign| sign.index).collect(),
+            })
+            .collect();
+        // Keep the report stable regardless of the hashmap order
+        collisions.sort_by_key(|collision| collision.indices[0]);
+
+        CollisionReport { collisions }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.collisions.is_empty()
+    }
+
+    /// Number of blocks that share their weak hash with another block
+    pub fn colliding_blocks(&self) -> usize {
+        self.collisions
+            .iter()
+            .map(|collision| collision.indices.len())
+            .sum()
+    }
 }
 
 impl fmt::Debug for Signature {
@@ -30,7 +105,7 @@ impl fmt::Debug for Signature {
             f,
             "\nindex: {} \nbytes {:?} \nchecksum {}\n",
             self.index,
-            std::str::from_utf8(&self.bytes),
+            self.bytes.as_deref().map(std::str::from_utf8),
             joined_checksum
         )
     }
@@ -41,14 +116,15 @@ impl Signature {
         src_path: &str,
         chunk_size: usize,
         algorithm: &str,
+        mode: SignMode,
+        format: Format,
         output_path: &str,
-    ) -> Result<u32, DiffError> {
+    ) -> Result<CollisionReport, DiffError> {
         // Generate them
-        let (signatures, collisions) = Signature::gen_sigs(src_path, chunk_size, algorithm)?;
+        let (signatures, collisions) = Signature::gen_sigs(src_path, chunk_size, algorithm, mode)?;
 
         // Write to the file
-        let f = File::create(output_path)?;
-        serde_json::to_writer(&f, &signatures)?;
+        save_signatures(output_path, &signatures, format)?;
 
         Ok(collisions)
     }
@@ -57,82 +133,281 @@ impl Signature {
         src_path: &str,
         chunk_size: usize,
         algorithm: &str,
-    ) -> Result<(HashMap<u32, Signature>, u32), DiffError> {
-        let mut f = File::open(src_path)?;
-        let mut buffer = Vec::<u8>::new();
-        f.read_to_end(&mut buffer)?; // Possible improvement with buffered reader
+        mode: SignMode,
+    ) -> Result<(SignatureSet, CollisionReport), DiffError> {
+        let f = open_file(src_path)?;
+        Signature::gen_sigs_from_reader(BufReader::new(f), chunk_size, algorithm, mode)
+    }
+
+    /// Generate the signatures of data that's already in memory
+    pub fn gen_sigs_from_bytes(
+        data: &[u8],
+        chunk_size: usize,
+        algorithm: &str,
+        mode: SignMode,
+    ) -> Result<(SignatureSet, CollisionReport), DiffError> {
+        Signature::gen_sigs_from_reader(data, chunk_size, algorithm, mode)
+    }
+
+    /// Generate the signatures as the input is read, so only a 4 MiB batch
+    /// of blocks (or a single block, if it's bigger) is held in memory
+    /// regardless of the input size.
+    pub fn gen_sigs_from_reader<R: Read>(
+        reader: R,
+        chunk_size: usize,
+        algorithm: &str,
+        mode: SignMode,
+    ) -> Result<(SignatureSet, CollisionReport), DiffError> {
+        Signature::gen_sigs_chunked(
+            reader,
+            chunk_size,
+            Chunking::Fixed,
+            algorithm,
+            StrongHashKind::Blake2s.name(),
+            mode,
+        )
+    }
 
+    /// Generate the signatures of blocks cut with the given chunking. For
+    /// content defined chunking `chunk_size` is the average block size.
+    /// The block checksums are made with the `strong_hash` spec.
+    pub fn gen_sigs_chunked<R: Read>(
+        reader: R,
+        chunk_size: usize,
+        chunking: Chunking,
+        algorithm: &str,
+        strong_hash: &str,
+        mode: SignMode,
+    ) -> Result<(SignatureSet, CollisionReport), DiffError> {
+        Signature::gen_sigs_parallel(
+            reader,
+            chunk_size,
+            chunking,
+            algorithm,
+            strong_hash,
+            mode,
+            1,
+        )
+    }
+
+    /// Same as `gen_sigs_chunked`, with the blocks hashed on up to `jobs`
+    /// threads. The input is still read in order and the signatures come
+    /// out exactly as they would from a single thread.
+    ///
+    /// `jobs` is capped at the number of CPUs. Every thread gets its own
+    /// 4 MiB batch of blocks, so up to `jobs` x 4 MiB of the input (plus
+    /// one block) is held in memory at once.
+    pub fn gen_sigs_parallel<R: Read>(
+        reader: R,
+        chunk_size: usize,
+        chunking: Chunking,
+        algorithm: &str,
+        strong_hash: &str,
+        mode: SignMode,
+        jobs: usize,
+    ) -> Result<(SignatureSet, CollisionReport), DiffError> {
+        check_chunk_size(chunk_size)?;
+        let jobs = jobs.clamp(1, max_jobs());
+        let algo = registry::create(algorithm)?;
+        let strong_hash = StrongHash::from_spec(strong_hash)?;
+        let mut reader = Blake2Reader::new(reader);
         let mut signatures = HashMap::new();
+        let mut batch = BlockBatch::default();
         let mut signature_index = 0;
-        let mut collisions = 0;
 
-        for index in (0..buffer.len()).step_by(chunk_size) {
-            // Check if index is stil valid
-            if index + chunk_size > buffer.len() {
-                break;
-            } else {
-                let chunk = &buffer[index..index + chunk_size];
-                let result =
-                    Signature::add_next_sign(&algorithm, signature_index, chunk, &mut signatures)?;
-                match result {
-                    true => collisions += 1,
-                    false => (),
-                }
-                signature_index += 1;
+        let mut add_block = |offset: u64, chunk: &[u8]| {
+            batch.push(offset, chunk);
+            if batch.is_full(jobs) {
+                signature_index = batch.hash(
+                    signature_index,
+                    algorithm,
+                    &strong_hash,
+                    mode,
+                    jobs,
+                    &mut signatures,
+                )?;
             }
+            Ok(())
+        };
+        match GearChunker::from_chunking(chunking, chunk_size)? {
+            Some(chunker) => chunker.for_each_block(&mut reader, &mut add_block)?,
+            None => for_each_fixed_block(&mut reader, chunk_size, &mut add_block)?,
         }
+        // Hash whatever the last batch holds
+        batch.hash(
+            signature_index,
+            algorithm,
+            &strong_hash,
+            mode,
+            jobs,
+            &mut signatures,
+        )?;
+        // Make sure the digest covers the whole input
+        std::io::copy(&mut reader, &mut std::io::sink())?;
+
+        let (basis_digest, basis_len) = reader.finalize();
+        let header = Header {
+            chunk_size,
+            chunking,
+            algorithm: registry::to_spec(registry::parse_spec(algorithm)?.0, &algo.params()),
+            strong_hash: strong_hash.to_spec(),
+            basis_len,
+            basis_digest,
+        };
 
-        Ok((signatures, collisions))
+        let collisions = CollisionReport::from_signatures(&signatures);
+        let signature_set = SignatureSet { header, signatures };
+        Ok((signature_set, collisions))
     }
 
-    /// Create a new Signature and add it to the signatures hashmap
-    fn add_next_sign(
-        algorithm: &str,
-        index: u32,
+    /// Create the Signature of a block along with its weak hash
+    fn hash_block(
+        algo: &mut dyn Algorithm,
+        strong_hash: &StrongHash,
+        index: u64,
+        offset: u64,
         chunk: &[u8],
-        signatures: &mut HashMap<u32, Signature>,
-    ) -> Result<bool, DiffError> {
-        let weak_hash = match algorithm {
-            "fletcher" => Fletcher32::new().get_chunk_hash(chunk)?,
-            _ => Adler32::new().get_chunk_hash(chunk)?,
-        };
+        mode: SignMode,
+    ) -> Result<(u32, Signature), DiffError> {
+        let weak_hash = algo.get_chunk_hash(chunk)?;
 
-        let checksum = get_blake2(chunk.to_vec())?;
-        let bytes = chunk.to_vec();
-
-        if signatures.contains_key(&weak_hash) {
-            // We found the hash in there already,
-            // Let's confirm it's not a collision
-            if let Some(sign) = &signatures.get(&weak_hash) {
-                if &bytes != &sign.bytes {
-                    warn!("Key already exists in the signatures, Skipping the block");
-                    return Ok(true);
-                } else {
-                    // Hash already present, move on
-                    return Ok(false);
-                }                
-            }
-        }
+        let checksum = strong_hash.digest(chunk);
+        let bytes = match mode {
+            SignMode::Full => Some(chunk.to_vec()),
+            SignMode::Lean => None,
+        };
 
         let signature = Signature {
             index,
+            offset,
+            len: chunk.len() as u64,
             checksum,
             bytes,
         };
+        Ok((weak_hash, signature))
+    }
+
+    /// Add a Signature to the signatures index. Signatures have to be added
+    /// in block order for the first of identical blocks to be the one kept.
+    fn add_sign(signatures: &mut SignatureIndex, weak_hash: u32, signature: Signature) {
+        let entries = signatures.entry(weak_hash).or_default();
+        // Identical blocks don't need a second entry, any of them will do
+        if entries
+            .iter()
+            .any(|sign| sign.checksum == signature.checksum)
+        {
+            return;
+        }
+        if !entries.is_empty() {
+            debug!(
+                "Block {} shares the weak hash {}",
+                signature.index, weak_hash
+            );
+        }
+        entries.push(signature);
+    }
+}
+
+/// More threads than CPUs only adds to the memory held in batches
+fn max_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |cpus| cpus.get())
+}
+
+/// Blocks read ahead of hashing, so their hashes can be worked out on
+/// several threads at once
+#[derive(Default)]
+struct BlockBatch {
+    data: Vec<u8>,
+    /// Offset in the input and end in `data` of every block
+    blocks: Vec<(u64, usize)>,
+}
+
+impl BlockBatch {
+    fn push(&mut self, offset: u64, chunk: &[u8]) {
+        self.data.extend_from_slice(chunk);
+        self.blocks.push((offset, self.data.len()));
+    }
+
+    /// Whether there's enough to keep every thread busy for a while
+    fn is_full(&self, jobs: usize) -> bool {
+        self.blocks.len() >= jobs.saturating_mul(BATCH_BLOCKS)
+            || self.data.len() >= jobs.saturating_mul(BATCH_BYTES)
+    }
+
+    /// Offset and bytes of the nth block
+    fn block(&self, n: usize) -> (u64, &[u8]) {
+        let start = match n {
+            0 => 0,
+            _ => self.blocks[n - 1].1,
+        };
+        let (offset, end) = self.blocks[n];
+        (offset, &self.data[start..end])
+    }
 
-        signatures.insert(weak_hash, signature);
+    /// Hash the blocks, numbering them from `first_index`, and add them to
+    /// the index in order. Returns the index of the next block and leaves
+    /// the batch empty.
+    fn hash(
+        &mut self,
+        first_index: u64,
+        algorithm: &str,
+        strong_hash: &StrongHash,
+        mode: SignMode,
+        jobs: usize,
+        signatures: &mut SignatureIndex,
+    ) -> Result<u64, DiffError> {
+        let count = self.blocks.len();
+        let per_job = count.div_ceil(jobs).max(1);
+        let batch = &*self;
+        let hash_range = |from: usize| -> Result<Vec<(u32, Signature)>, DiffError> {
+            // Rolling hashes keep state, every thread needs its own
+            let mut algo = registry::create(algorithm)?;
+            (from..count.min(from + per_job))
+                .map(|n| {
+                    let (offset, chunk) = batch.block(n);
+                    let index = first_index + n as u64;
+                    Signature::hash_block(&mut *algo, strong_hash, index, offset, chunk, mode)
+                })
+                .collect()
+        };
 
-        Ok(false)
+        let hashed = match count <= per_job {
+            true => vec![hash_range(0)?],
+            false => std::thread::scope(|scope| {
+                let handles: Vec<_> = (0..count)
+                    .step_by(per_job)
+                    .map(|from| scope.spawn(move || hash_range(from)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| {
+                        handle
+                            .join()
+                            .unwrap_or_else(|err| std::panic::resume_unwind(err))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })?,
+        };
+        for (weak_hash, signature) in hashed.into_iter().flatten() {
+            Signature::add_sign(signatures, weak_hash, signature);
+        }
+
+        self.data.clear();
+        self.blocks.clear();
+        Ok(first_index + count as u64)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::chunking::MAX_CHUNK_SIZE;
+    use crate::format::{load_signatures, write_signatures};
+    use crate::utils::{get_blake2, splitmix64};
     use rand::{thread_rng, Rng};
     use serde_json;
     use std::{
-        fs::{remove_file, write, File},
+        fs::{read_to_string, remove_file, write, File},
         io::BufReader,
     };
 
@@ -193,6 +468,20 @@ mod test {
         test_gen_sigs_save(chunk_size, algorithm);
     }
 
+    #[test]
+    fn test_gen_sigs_save_binary_adler_size4() {
+        let chunk_size = 4;
+        let algorithm = "adler";
+        test_gen_sigs_save_binary(chunk_size, algorithm);
+    }
+
+    #[test]
+    fn test_gen_sigs_save_binary_fletcher_size8() {
+        let chunk_size = 8;
+        let algorithm = "fletcher";
+        test_gen_sigs_save_binary(chunk_size, algorithm);
+    }
+
     #[test]
     fn test_gen_sigs_adler_size5() {
         let chunk_size = 5;
@@ -221,6 +510,20 @@ mod test {
         test_gen_sigs(chunk_size, algorithm);
     }
 
+    #[test]
+    fn test_gen_sigs_lean_adler_size4() {
+        let chunk_size = 4;
+        let algorithm = "adler";
+        test_gen_sigs_lean(chunk_size, algorithm);
+    }
+
+    #[test]
+    fn test_gen_sigs_lean_fletcher_size7() {
+        let chunk_size = 7;
+        let algorithm = "fletcher";
+        test_gen_sigs_lean(chunk_size, algorithm);
+    }
+
     #[test]
     fn test_add_next_sign_adler_size3() {
         let chunk_size = 3;
@@ -261,25 +564,79 @@ mod test {
         write(&tmp_in_file, data).unwrap();
 
         // Write the signatures
-        Signature::gen_sigs_save(&tmp_in_file, chunk_size, algorithm, tmp_out_file.as_str())
-            .unwrap();
+        Signature::gen_sigs_save(
+            &tmp_in_file,
+            chunk_size,
+            algorithm,
+            SignMode::Full,
+            Format::Json,
+            tmp_out_file.as_str(),
+        )
+        .unwrap();
 
         // Grab signatures directly
-        let (signatures, _) = Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm).unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Full).unwrap();
 
         // Load the other set of signatures from file
         let f = File::open(tmp_out_file.clone()).unwrap();
         let reader = BufReader::new(f);
-        let loaded_signs: HashMap<u32, Signature> = serde_json::from_reader(reader).unwrap();
+        let loaded_signs: SignatureSet = serde_json::from_reader(reader).unwrap();
 
-        // Length should be the same
-        assert_eq!(signatures.len(), loaded_signs.len());
+        // Header and length should be the same
+        assert_eq!(signatures.header, loaded_signs.header);
+        assert_eq!(signatures.signatures.len(), loaded_signs.signatures.len());
         // Check if data matches
-        for (hash, sign) in signatures {
-            let loaded_sign = loaded_signs.get(&hash).unwrap();
-            assert_eq!(sign.index, loaded_sign.index);
-            assert_eq!(sign.checksum, loaded_sign.checksum);
-            assert_eq!(sign.bytes, loaded_sign.bytes);
+        for (hash, signs) in signatures.signatures {
+            let loaded = loaded_signs.signatures.get(&hash).unwrap();
+            assert_eq!(signs.len(), loaded.len());
+            for (sign, loaded_sign) in signs.iter().zip(loaded) {
+                assert_eq!(sign.index, loaded_sign.index);
+                assert_eq!(sign.checksum, loaded_sign.checksum);
+                assert_eq!(sign.bytes, loaded_sign.bytes);
+            }
+        }
+
+        // Cleanup
+        remove_file(tmp_in_file).unwrap();
+        remove_file(tmp_out_file).unwrap();
+    }
+
+    fn test_gen_sigs_save_binary(chunk_size: usize, algorithm: &str) {
+        // Create the test files
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        let tmp_out_file = format!("{}_{}", TEST_SIGN_FILE, get_rnum());
+        // Write some data
+        let data = "Far far away, behind the word mountains, far from the countries Vokalia and Consonantia, there live the blind texts";
+        write(&tmp_in_file, data).unwrap();
+
+        // Write the signatures
+        for mode in [SignMode::Full, SignMode::Lean] {
+            Signature::gen_sigs_save(
+                &tmp_in_file,
+                chunk_size,
+                algorithm,
+                mode,
+                Format::Binary,
+                tmp_out_file.as_str(),
+            )
+            .unwrap();
+            let (signatures, _) =
+                Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, mode).unwrap();
+
+            // Load them back, the format is picked up from the file
+            let loaded_signs = load_signatures(&tmp_out_file).unwrap();
+            assert_eq!(signatures.header, loaded_signs.header);
+            assert_eq!(signatures.signatures.len(), loaded_signs.signatures.len());
+            for (hash, signs) in signatures.signatures {
+                let loaded = loaded_signs.signatures.get(&hash).unwrap();
+                assert_eq!(signs.len(), loaded.len());
+                for (sign, loaded_sign) in signs.iter().zip(loaded) {
+                    assert_eq!(sign.index, loaded_sign.index);
+                    assert_eq!(sign.checksum, loaded_sign.checksum);
+                    assert_eq!(sign.bytes, loaded_sign.bytes);
+                }
+            }
         }
 
         // Cleanup
@@ -294,26 +651,65 @@ mod test {
         let data = "A kangaroo is really just a rabbit on steroids. When transplanting seedlings, candied teapots will make the task easier.";
         write(&tmp_in_file, data).unwrap();
         // Grab the signatures from the function
-        let (signatures, _) = Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm).unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Full).unwrap();
         // Iterate over them and confirm data
         let buffer = data.as_bytes();
         for index in (0..buffer.len()).step_by(chunk_size) {
             let chunk = &buffer[index..index + chunk_size];
-            let weak_hash = match algorithm {
-                "fletcher" => Fletcher32::new().get_chunk_hash(chunk).unwrap(),
-                _ => Adler32::new().get_chunk_hash(chunk).unwrap(),
-            };
+            let weak_hash = registry::create(algorithm)
+                .unwrap()
+                .get_chunk_hash(chunk)
+                .unwrap();
             let checksum = get_blake2(chunk.to_vec()).unwrap();
 
             // Grab the appropriate signature
-            let sign = signatures.get(&weak_hash).unwrap();
+            let sign = find_sign(signatures.signatures.get(&weak_hash).unwrap(), &checksum);
             // And test
             assert_eq!(checksum, sign.checksum);
-            assert_eq!(chunk, sign.bytes);
+            assert_eq!(Some(chunk.to_vec()), sign.bytes);
+        }
+
+        // Cleanup
+        remove_file(tmp_in_file).unwrap();
+    }
+
+    fn test_gen_sigs_lean(chunk_size: usize, algorithm: &str) {
+        // Create the test files
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        let tmp_out_file = format!("{}_{}", TEST_SIGN_FILE, get_rnum());
+        // Write some test data
+        let data = "Sometimes it is better to just walk away from things and go back to them later when you're in a better frame of mind.";
+        write(&tmp_in_file, data).unwrap();
+        // Save the lean signatures
+        Signature::gen_sigs_save(
+            &tmp_in_file,
+            chunk_size,
+            algorithm,
+            SignMode::Lean,
+            Format::Json,
+            tmp_out_file.as_str(),
+        )
+        .unwrap();
+        // Nothing from the basis file should end up in the output
+        let saved = read_to_string(&tmp_out_file).unwrap();
+        assert!(!saved.contains("bytes"));
+        let loaded_signs: SignatureSet = serde_json::from_str(&saved).unwrap();
+        let (full_signs, _) =
+            Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Full).unwrap();
+        let full_signs = full_signs.signatures;
+        assert_eq!(full_signs.len(), loaded_signs.signatures.len());
+        for (hash, signs) in loaded_signs.signatures {
+            for sign in signs {
+                let full_sign = find_sign(full_signs.get(&hash).unwrap(), &sign.checksum);
+                assert_eq!(sign.index, full_sign.index);
+                assert_eq!(sign.bytes, None);
+            }
         }
 
         // Cleanup
         remove_file(tmp_in_file).unwrap();
+        remove_file(tmp_out_file).unwrap();
     }
 
     fn test_add_next_sign(chunk_size: usize, algorithm: &str) {
@@ -322,24 +718,323 @@ mod test {
             "The random sentence generator generated a random sentence about a random sentence"
                 .as_bytes();
         let mut hmap = HashMap::new();
+        let mut algo = registry::create(algorithm).unwrap();
         // Let's start
         for index in (0..data.len()).step_by(chunk_size) {
             let chunk = &data[index..index + chunk_size];
-            let weak_hash = match algorithm {
-                "fletcher" => Fletcher32::new().get_chunk_hash(chunk).unwrap(),
-                _ => Adler32::new().get_chunk_hash(chunk).unwrap(),
-            };
-            Signature::add_next_sign(algorithm, index as u32, chunk, &mut hmap).unwrap();
+            let weak_hash = registry::create(algorithm)
+                .unwrap()
+                .get_chunk_hash(chunk)
+                .unwrap();
+            let (hash, sign) = Signature::hash_block(
+                &mut *algo,
+                &StrongHash::default(),
+                index as u64,
+                index as u64,
+                chunk,
+                SignMode::Full,
+            )
+            .unwrap();
+            assert_eq!(hash, weak_hash);
+            Signature::add_sign(&mut hmap, hash, sign);
             // Test if it exists in the hashmap
             assert!(hmap.contains_key(&weak_hash));
             // Now ensure that the data is good
-            let sign = hmap.get(&weak_hash).unwrap();
             let checksum = get_blake2(chunk.to_vec()).unwrap();
-            assert_eq!(chunk, sign.bytes);
-            assert_eq!(checksum, sign.checksum);
+            let sign = find_sign(hmap.get(&weak_hash).unwrap(), &checksum);
+            assert_eq!(Some(chunk.to_vec()), sign.bytes);
+        }
+    }
+
+    #[test]
+    fn test_gen_sigs_keeps_collisions() {
+        let chunk_size = 3;
+        // "abc" and "b`d" have the same byte sum and weighted sum,
+        // so both rolling hashes end up colliding
+        let data = "abcb`dabc";
+        for algorithm in ["adler", "fletcher"] {
+            let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+            write(&tmp_in_file, data).unwrap();
+
+            let (signatures, collisions) =
+                Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Full).unwrap();
+
+            // Both blocks are kept under the same weak hash,
+            // the repeated third block is not stored again
+            assert_eq!(signatures.signatures.len(), 1);
+            let signs = signatures.signatures.values().next().unwrap();
+            assert_eq!(signs.len(), 2);
+            assert_eq!(Some(b"abc".to_vec()), signs[0].bytes);
+            assert_eq!(Some(b"b`d".to_vec()), signs[1].bytes);
+
+            // And the report says which blocks share the hash
+            assert_eq!(collisions.collisions.len(), 1);
+            assert_eq!(collisions.collisions[0].indices, vec![0, 1]);
+            assert_eq!(collisions.colliding_blocks(), 2);
+
+            remove_file(tmp_in_file).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_gen_sigs_header() {
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        let data = "The header should describe how the signatures were made";
+        write(&tmp_in_file, data).unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, 6, "fletcher", SignMode::Lean).unwrap();
+        let header = signatures.header;
+        assert_eq!(header.chunk_size, 6);
+        assert_eq!(header.algorithm, "fletcher");
+        assert_eq!(header.strong_hash, StrongHashKind::Blake2s.name());
+        assert_eq!(header.basis_len, data.len() as u64);
+        assert_eq!(
+            header.basis_digest,
+            get_blake2(data.as_bytes().to_vec()).unwrap()
+        );
+
+        // Unknown names are an error
+        let result = Signature::gen_sigs(&tmp_in_file, 6, "fletcer", SignMode::Lean);
+        assert!(matches!(result, Err(DiffError::UnknownAlgorithm(_))));
+        remove_file(tmp_in_file).unwrap();
+    }
+
+    #[test]
+    fn test_gen_sigs_chunk_size() {
+        let data = vec![7; 3 * 1024 * 1024 + 5];
+        // Blocks of a few MiB are fine
+        let (signatures, _) =
+            Signature::gen_sigs_from_reader(data.as_slice(), 1024 * 1024, "adler", SignMode::Lean)
+                .unwrap();
+        assert_eq!(signatures.header.chunk_size, 1024 * 1024);
+        let mut lens: Vec<u64> = signatures
+            .signatures
+            .values()
+            .flatten()
+            .map(|s| s.len)
+            .collect();
+        lens.sort();
+        assert_eq!(lens, vec![5, 1024 * 1024]);
+
+        for chunk_size in [0, MAX_CHUNK_SIZE + 1] {
+            let result = Signature::gen_sigs_from_reader(
+                data.as_slice(),
+                chunk_size,
+                "adler",
+                SignMode::Lean,
+            );
+            assert!(matches!(result, Err(DiffError::InvalidChunkSize(_))));
+        }
+    }
+
+    #[test]
+    fn test_gen_sigs_partial_block() {
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        // Two whole blocks and a two byte tail
+        write(&tmp_in_file, "abcdefghij").unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, 4, "adler", SignMode::Full).unwrap();
+        let signs: Vec<&Signature> = signatures.signatures.values().flatten().collect();
+        assert_eq!(signs.len(), 3);
+        let checksum = get_blake2(b"ij".to_vec()).unwrap();
+        let tail = signs.iter().find(|sign| sign.index == 2).unwrap();
+        assert_eq!(tail.checksum, checksum);
+        assert_eq!(tail.bytes, Some(b"ij".to_vec()));
+
+        // Files shorter than a block still get a signature
+        write(&tmp_in_file, "ab").unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, 4, "fletcher", SignMode::Lean).unwrap();
+        let signs: Vec<&Signature> = signatures.signatures.values().flatten().collect();
+        assert_eq!(signs.len(), 1);
+        assert_eq!(signs[0].index, 0);
+        assert_eq!(signs[0].checksum, get_blake2(b"ab".to_vec()).unwrap());
+        remove_file(tmp_in_file).unwrap();
+    }
+
+    #[test]
+    fn test_gen_sigs_chunked() {
+        let data = "Content defined blocks carry their own offset and length, since they \
+                    can't be worked out from the index and the chunk size like fixed blocks";
+        let (signatures, _) = Signature::gen_sigs_chunked(
+            data.as_bytes(),
+            16,
+            Chunking::gear(16),
+            "adler",
+            "sha256:len=8",
+            SignMode::Full,
+        )
+        .unwrap();
+        assert_eq!(signatures.header.chunking, Chunking::gear(16));
+
+        // The blocks are back to back and cover the whole input
+        let mut signs: Vec<&Signature> = signatures.signatures.values().flatten().collect();
+        signs.sort_by_key(|sign| sign.index);
+        let mut offset = 0;
+        for sign in signs {
+            assert_eq!(sign.offset, offset);
+            let end = (sign.offset + sign.len) as usize;
+            assert_eq!(
+                sign.bytes.as_deref(),
+                Some(&data.as_bytes()[offset as usize..end])
+            );
+            offset += sign.len;
+        }
+        assert_eq!(offset as usize, data.len());
+    }
+
+    #[test]
+    fn test_gen_sigs_parallel() {
+        // Few distinct bytes give plenty of repeated blocks and weak hash
+        // collisions, and enough blocks to fill several batches
+        let mut state = 7;
+        let data: Vec<u8> = (0..80_000)
+            .map(|_| b"abcd"[(splitmix64(&mut state) % 4) as usize])
+            .collect();
+        for chunking in [Chunking::Fixed, Chunking::gear(16)] {
+            let sign = |jobs| {
+                let (signatures, collisions) = Signature::gen_sigs_parallel(
+                    data.as_slice(),
+                    4,
+                    chunking,
+                    "adler",
+                    "blake3",
+                    SignMode::Full,
+                    jobs,
+                )
+                .unwrap();
+                let mut bytes = Vec::new();
+                write_signatures(&mut bytes, &signatures, Format::Binary).unwrap();
+                (bytes, collisions.collisions.len())
+            };
+            let single = sign(1);
+            assert!(single.1 > 0);
+            for jobs in [0, 2, 3, 8, usize::MAX] {
+                assert!(sign(jobs) == single, "{} jobs differ", jobs);
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_batch_threads() {
+        // Hash the batch directly, gen_sigs_parallel caps jobs at the CPUs
+        let strong_hash = StrongHash::from_spec("blake3").unwrap();
+        let hash = |jobs| {
+            let mut batch = BlockBatch::default();
+            for n in 0..100u8 {
+                batch.push(n as u64 * 4, &[n % 7; 4]);
+            }
+            assert!(!batch.is_full(usize::MAX));
+            let mut signatures = HashMap::new();
+            let next = batch
+                .hash(
+                    5,
+                    "adler",
+                    &strong_hash,
+                    SignMode::Lean,
+                    jobs,
+                    &mut signatures,
+                )
+                .unwrap();
+            assert_eq!(next, 105);
+            assert!(batch.blocks.is_empty());
+            let mut signs: Vec<_> = signatures.into_values().flatten().collect();
+            signs.sort_by_key(|sign| sign.index);
+            signs
+                .into_iter()
+                .map(|sign| (sign.index, sign.offset, sign.checksum))
+                .collect::<Vec<_>>()
+        };
+        let single = hash(1);
+        assert_eq!(single.len(), 7);
+        for jobs in [3, 8, 1000] {
+            assert_eq!(hash(jobs), single, "{} jobs differ", jobs);
+        }
+    }
+
+    #[test]
+    fn test_collision_report_empty() {
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        write(&tmp_in_file, "abcdefghijkl").unwrap();
+        let (_, collisions) =
+            Signature::gen_sigs(&tmp_in_file, 4, "adler", SignMode::Lean).unwrap();
+        assert!(collisions.is_empty());
+        assert_eq!(collisions.colliding_blocks(), 0);
+        remove_file(tmp_in_file).unwrap();
+    }
+
+    #[test]
+    fn test_gen_sigs_from_reader() {
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        let data = "Signatures from a reader should match the ones made from a path";
+        write(&tmp_in_file, data).unwrap();
+        let (expected, _) = Signature::gen_sigs(&tmp_in_file, 5, "adler", SignMode::Full).unwrap();
+
+        // Reader that hands out a few bytes at a time
+        let reader = ShortReader {
+            data: data.as_bytes(),
+            step: 3,
+        };
+        let (signatures, _) =
+            Signature::gen_sigs_from_reader(reader, 5, "adler", SignMode::Full).unwrap();
+        assert_eq!(signatures.header, expected.header);
+        assert_eq!(signatures.signatures.len(), expected.signatures.len());
+        for (weak_hash, signs) in &expected.signatures {
+            for sign in signs {
+                let found = find_sign(&signatures.signatures[weak_hash], &sign.checksum);
+                assert_eq!(found.index, sign.index);
+                assert_eq!(found.bytes, sign.bytes);
+            }
+        }
+        remove_file(tmp_in_file).unwrap();
+    }
+
+    #[test]
+    fn test_gen_sigs_from_bytes() {
+        let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+        let data = "Signatures of a buffer are the same as the ones of a file";
+        write(&tmp_in_file, data).unwrap();
+        let (expected, _) = Signature::gen_sigs(&tmp_in_file, 6, "adler", SignMode::Full).unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs_from_bytes(data.as_bytes(), 6, "adler", SignMode::Full).unwrap();
+
+        let mut expected_bytes = Vec::new();
+        write_signatures(&mut expected_bytes, &expected, Format::Binary).unwrap();
+        let mut bytes = Vec::new();
+        write_signatures(&mut bytes, &signatures, Format::Binary).unwrap();
+        assert_eq!(bytes, expected_bytes);
+        remove_file(tmp_in_file).unwrap();
+    }
+
+    #[test]
+    fn test_gen_sigs_from_reader_large() {
+        // 8 MiB of the same byte, only a single distinct block is kept
+        let len = 8 * 1024 * 1024;
+        let reader = std::io::repeat(b'x').take(len);
+        let (signatures, _) =
+            Signature::gen_sigs_from_reader(reader, 1024, "adler", SignMode::Lean).unwrap();
+        assert_eq!(signatures.header.basis_len, len);
+        assert_eq!(signatures.signatures.len(), 1);
+    }
+
+    struct ShortReader<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for ShortReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = self.step.min(buf.len()).min(self.data.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
         }
     }
 
+    fn find_sign<'a>(signs: &'a [Signature], checksum: &[u8]) -> &'a Signature {
+        signs.iter().find(|sign| sign.checksum == checksum).unwrap()
+    }
+
     fn get_rnum() -> u32 {
         let mut rng = thread_rng();
         rng.gen()
@@ -1,5 +1,6 @@
 use crate::error::DiffError;
 use blake2::{Blake2s256, Digest};
+use std::io::{Read, Write};
 
 pub fn get_blake2(chunk: Vec<u8>) -> Result<Vec<u8>, DiffError> {
     let mut hasher = Blake2s256::new();
@@ -8,3 +9,108 @@ pub fn get_blake2(chunk: Vec<u8>) -> Result<Vec<u8>, DiffError> {
 
     Ok(result)
 }
+
+/// Blake2 digest of everything the reader has to offer, along with its length
+pub fn get_blake2_from_reader<R: Read>(mut reader: R) -> Result<(Vec<u8>, u64), DiffError> {
+    let mut hasher = Blake2s256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut len = 0;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        len += read as u64;
+    }
+    let result = hasher.finalize().as_slice().to_vec();
+
+    Ok((result, len))
+}
+
+/// Reader that keeps a Blake2 digest and count of everything read through it
+pub struct Blake2Reader<R: Read> {
+    inner: R,
+    hasher: Blake2s256,
+    len: u64,
+}
+
+impl<R: Read> Blake2Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Blake2Reader {
+            inner,
+            hasher: Blake2s256::new(),
+            len: 0,
+        }
+    }
+
+    /// Digest and length of everything that was read
+    pub fn finalize(self) -> (Vec<u8>, u64) {
+        (self.hasher.finalize().as_slice().to_vec(), self.len)
+    }
+}
+
+impl<R: Read> Read for Blake2Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.len += read as u64;
+        Ok(read)
+    }
+}
+
+/// Fill the buffer from the reader, stops short only at the end of the input.
+/// Returns how many bytes were read.
+pub fn read_block<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, DiffError> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(filled)
+}
+
+/// Writer that keeps a Blake2 digest of everything written through it
+pub struct Blake2Writer<W: Write> {
+    inner: W,
+    hasher: Blake2s256,
+}
+
+impl<W: Write> Blake2Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Blake2Writer {
+            inner,
+            hasher: Blake2s256::new(),
+        }
+    }
+
+    /// Hand back the inner writer along with the digest of what was written
+    pub fn finalize(self) -> (W, Vec<u8>) {
+        (self.inner, self.hasher.finalize().as_slice().to_vec())
+    }
+}
+
+impl<W: Write> Write for Blake2Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Next number of a splitmix64 sequence, used to build reproducible tables
+pub fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -1,11 +1,27 @@
+use proptest::collection::vec;
+use proptest::prelude::*;
 use rand::{thread_rng, Rng};
-use rustdiff::delta::gen_delta_from_file;
-use rustdiff::patch::patch_file_with_delta;
-use rustdiff::sign::Signature;
+use rustdiff::algorithms::algorithm::Algorithm;
+use rustdiff::algorithms::buzhash::DEFAULT_SEED;
+use rustdiff::algorithms::registry::{self, RollingHashKind};
+use rustdiff::algorithms::strong_hash::StrongHashKind;
+use rustdiff::chunking::{ChunkSize, Chunking};
+use rustdiff::delta::{gen_delta_from_bytes, gen_delta_from_file, Delta};
+use rustdiff::error::DiffError;
+use rustdiff::format::{
+    load_delta, load_signatures, read_delta, read_signatures, save_signatures, write_delta,
+    write_signatures, Format,
+};
+use rustdiff::patch::{patch_file_with_delta, patch_from_bytes};
+use rustdiff::sign::{SignMode, Signature};
+use rustdiff::utils::splitmix64;
 use std::fs::{read_to_string, remove_file, write, File};
+use std::io::{BufReader, Seek, SeekFrom, Write};
+use std::process::{Command, Stdio};
 
 const TEST_IN_FILE: &str = "data/tmp/patch_test_input.txt";
 const TEST_M_IN_FILE: &str = "data/tmp/patch_test_m_input.txt";
+const TEST_SIGN_FILE: &str = "data/tmp/patch_test_signs.bin";
 const TEST_DELTA_FILE: &str = "data/tmp/patch_test_delta.json";
 const TEST_OUT_FILE: &str = "data/tmp/patch_test_output.json";
 
@@ -33,6 +49,566 @@ fn test_modify_remove_data_all() {
     }
 }
 
+#[test]
+fn test_colliding_blocks_all() {
+    // "abc" and "b`d" share a weak hash with both algorithms
+    test_colliding_blocks("adler");
+    test_colliding_blocks("fletcher");
+}
+
+#[test]
+fn test_partial_blocks_all() {
+    // None of the lengths line up with the chunk size
+    for chunk_size in 2..=16 {
+        test_partial_blocks(chunk_size, "adler");
+        test_partial_blocks(chunk_size, "fletcher");
+    }
+}
+
+#[test]
+fn test_rabin_karp_all() {
+    for chunk_size in 2..=16 {
+        test_modify_add_data(chunk_size, "rabin-karp");
+        test_modify_remove_data(chunk_size, "rabin-karp:base=31,modulus=65521");
+    }
+}
+
+#[test]
+fn test_buzhash_all() {
+    for chunk_size in 2..=40 {
+        test_modify_add_data(chunk_size, "buzhash");
+        test_modify_remove_data(chunk_size, "buzhash:seed=42");
+    }
+}
+
+#[test]
+fn test_content_defined_chunking_all() {
+    for chunk_size in 2..=32 {
+        test_content_defined_chunking(chunk_size, "adler", "blake2s256", Format::Binary);
+        test_content_defined_chunking(chunk_size, "buzhash", "xxh3-128:len=8", Format::Json);
+    }
+}
+
+#[test]
+fn test_large_chunk_sizes() {
+    let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+    let tmp_m_in_file = format!("{}_{}", TEST_M_IN_FILE, get_rnum());
+    let tmp_delta_file = format!("{}_{}", TEST_DELTA_FILE, get_rnum());
+    let tmp_out_file = format!("{}_{}", TEST_OUT_FILE, get_rnum());
+
+    let mut state = 3;
+    let data: Vec<u8> = (0..1024 * 1024 + 100)
+        .map(|_| splitmix64(&mut state) as u8)
+        .collect();
+    write(&tmp_in_file, &data).unwrap();
+    let mut modified_data = data.clone();
+    modified_data.splice(700_000..700_000, b"inserted".iter().copied());
+    write(&tmp_m_in_file, &modified_data).unwrap();
+
+    let auto = ChunkSize::Auto.resolve(Some(data.len() as u64)).unwrap();
+    assert_eq!(auto, 1024);
+    for chunk_size in [auto, 300, 64 * 1024, 512 * 1024] {
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, chunk_size, "adler", SignMode::Lean).unwrap();
+        assert_eq!(signatures.header.chunk_size, chunk_size);
+        gen_delta_from_file(&tmp_m_in_file, &tmp_delta_file, Format::Binary, signatures).unwrap();
+        patch_file_with_delta(
+            tmp_in_file.clone(),
+            tmp_delta_file.clone(),
+            tmp_out_file.clone(),
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(&tmp_out_file).unwrap(), modified_data);
+    }
+
+    // Cleanup
+    remove_file(tmp_in_file).unwrap();
+    remove_file(tmp_m_in_file).unwrap();
+    remove_file(tmp_delta_file).unwrap();
+    remove_file(tmp_out_file).unwrap();
+}
+
+#[test]
+fn test_parallel_signatures() {
+    let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+    let tmp_m_in_file = format!("{}_{}", TEST_M_IN_FILE, get_rnum());
+    let tmp_delta_file = format!("{}_{}", TEST_DELTA_FILE, get_rnum());
+    let tmp_out_file = format!("{}_{}", TEST_OUT_FILE, get_rnum());
+
+    let mut state = 5;
+    let data: Vec<u8> = (0..200_000).map(|_| splitmix64(&mut state) as u8).collect();
+    write(&tmp_in_file, &data).unwrap();
+    let mut modified_data = data.clone();
+    modified_data.splice(150_000..150_100, b"replaced".iter().copied());
+    write(&tmp_m_in_file, &modified_data).unwrap();
+
+    for chunking in [Chunking::Fixed, Chunking::gear(64)] {
+        let (signatures, _) = Signature::gen_sigs_parallel(
+            File::open(&tmp_in_file).unwrap(),
+            64,
+            chunking,
+            "rabin-karp",
+            "xxh3-128",
+            SignMode::Lean,
+            4,
+        )
+        .unwrap();
+        gen_delta_from_file(&tmp_m_in_file, &tmp_delta_file, Format::Binary, signatures).unwrap();
+        patch_file_with_delta(
+            tmp_in_file.clone(),
+            tmp_delta_file.clone(),
+            tmp_out_file.clone(),
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(&tmp_out_file).unwrap(), modified_data);
+    }
+
+    // Cleanup
+    remove_file(tmp_in_file).unwrap();
+    remove_file(tmp_m_in_file).unwrap();
+    remove_file(tmp_delta_file).unwrap();
+    remove_file(tmp_out_file).unwrap();
+}
+
+/// Slow without optimisations, CI runs it with `cargo test --release -- --ignored`
+#[test]
+#[ignore = "signs and patches a sparse file larger than 4 GiB"]
+fn test_sparse_round_trip() {
+    let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+    let tmp_m_in_file = format!("{}_{}", TEST_M_IN_FILE, get_rnum());
+    let tmp_sign_file = format!("{}_{}", TEST_SIGN_FILE, get_rnum());
+    let tmp_delta_file = format!("{}_{}", TEST_DELTA_FILE, get_rnum());
+    let tmp_out_file = format!("{}_{}", TEST_OUT_FILE, get_rnum());
+
+    // Data at the start and past 4 GiB, the rest is a hole of zeros
+    let far: u64 = (4 << 30) + (16 << 20);
+    let mut state = 11;
+    let near_data: Vec<u8> = (0..1 << 20).map(|_| splitmix64(&mut state) as u8).collect();
+    let far_data: Vec<u8> = (0..1 << 20).map(|_| splitmix64(&mut state) as u8).collect();
+    let mut basis = File::create(&tmp_in_file).unwrap();
+    basis.set_len(far + (32 << 20)).unwrap();
+    basis.write_all(&near_data).unwrap();
+    basis.seek(SeekFrom::Start(far)).unwrap();
+    basis.write_all(&far_data).unwrap();
+    drop(basis);
+
+    // The far data moves to the front of the modified file
+    let modified_data = [far_data.as_slice(), b"inserted", &near_data].concat();
+    write(&tmp_m_in_file, &modified_data).unwrap();
+
+    let (signatures, _) = Signature::gen_sigs_parallel(
+        BufReader::new(File::open(&tmp_in_file).unwrap()),
+        16 * 1024,
+        Chunking::gear(16 * 1024),
+        "adler",
+        "xxh3-128",
+        SignMode::Lean,
+        4,
+    )
+    .unwrap();
+    save_signatures(&tmp_sign_file, &signatures, Format::Binary).unwrap();
+    let signatures = load_signatures(&tmp_sign_file).unwrap();
+    gen_delta_from_file(&tmp_m_in_file, &tmp_delta_file, Format::Binary, signatures).unwrap();
+
+    // Ranges copied from past 4 GiB keep their 64 bit offsets
+    let delta = load_delta(&tmp_delta_file).unwrap();
+    assert!(delta.delta.iter().any(|op| matches!(
+        op,
+        Delta::CopyRange { offset, .. } if *offset > u32::MAX as u64
+    )));
+
+    patch_file_with_delta(
+        tmp_in_file.clone(),
+        tmp_delta_file.clone(),
+        tmp_out_file.clone(),
+    )
+    .unwrap();
+    assert!(std::fs::read(&tmp_out_file).unwrap() == modified_data);
+
+    // Cleanup
+    remove_file(tmp_in_file).unwrap();
+    remove_file(tmp_m_in_file).unwrap();
+    remove_file(tmp_sign_file).unwrap();
+    remove_file(tmp_delta_file).unwrap();
+    remove_file(tmp_out_file).unwrap();
+}
+
+#[test]
+fn test_in_memory_round_trip() {
+    let data = b"An original kept in memory, signed, diffed and patched in memory".repeat(20);
+    let mut modified = data.clone();
+    modified.splice(300..310, b"without temp files".iter().copied());
+    modified.truncate(1000);
+
+    for kind in RollingHashKind::ALL {
+        let (signatures, _) =
+            Signature::gen_sigs_from_bytes(&data, 16, kind.name(), SignMode::Lean).unwrap();
+        // Both sides only pass bytes around
+        let mut encoded = Vec::new();
+        write_signatures(&mut encoded, &signatures, Format::Binary).unwrap();
+        let signatures = read_signatures(encoded.as_slice()).unwrap();
+
+        let delta = gen_delta_from_bytes(&modified, &signatures).unwrap();
+        let mut encoded = Vec::new();
+        write_delta(&mut encoded, &delta, Format::Binary).unwrap();
+        let delta = read_delta(encoded.as_slice()).unwrap();
+
+        assert_eq!(patch_from_bytes(&data, &delta).unwrap(), modified);
+    }
+}
+
+#[test]
+fn test_saved_signatures() {
+    let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+    let tmp_m_in_file = format!("{}_{}", TEST_M_IN_FILE, get_rnum());
+    let tmp_sign_file = format!("{}_{}", TEST_SIGN_FILE, get_rnum());
+    let tmp_delta_file = format!("{}_{}", TEST_DELTA_FILE, get_rnum());
+    let tmp_out_file = format!("{}_{}", TEST_OUT_FILE, get_rnum());
+
+    let data = "Sign on one machine, diff on another and patch back on the first one";
+    write(&tmp_in_file, data).unwrap();
+    let modified_data = "Sign on one machine, diff on a second one and patch on the first one";
+    write(&tmp_m_in_file, modified_data).unwrap();
+
+    for (mode, format) in [
+        (SignMode::Lean, Format::Binary),
+        (SignMode::Lean, Format::Json),
+        (SignMode::Full, Format::Json),
+    ] {
+        // Only the saved signatures go to the side with the modified file
+        let (signatures, _) = Signature::gen_sigs(&tmp_in_file, 6, "buzhash", mode).unwrap();
+        save_signatures(&tmp_sign_file, &signatures, format).unwrap();
+        let signatures = load_signatures(&tmp_sign_file).unwrap();
+        gen_delta_from_file(&tmp_m_in_file, &tmp_delta_file, format, signatures).unwrap();
+
+        // And only the delta comes back to the original
+        patch_file_with_delta(
+            tmp_in_file.clone(),
+            tmp_delta_file.clone(),
+            tmp_out_file.clone(),
+        )
+        .unwrap();
+        assert_eq!(read_to_string(&tmp_out_file).unwrap(), modified_data);
+    }
+
+    // Cleanup
+    remove_file(tmp_in_file).unwrap();
+    remove_file(tmp_m_in_file).unwrap();
+    remove_file(tmp_sign_file).unwrap();
+    remove_file(tmp_delta_file).unwrap();
+    remove_file(tmp_out_file).unwrap();
+}
+
+#[test]
+fn test_strong_hash_all() {
+    for kind in StrongHashKind::ALL {
+        test_strong_hash(kind.name(), kind.digest_len());
+        test_strong_hash(&format!("{}:len=4", kind), 4);
+    }
+}
+
+#[test]
+fn test_algorithm_params_in_header() {
+    let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+    write(
+        &tmp_in_file,
+        "The params are recorded with the defaults filled in",
+    )
+    .unwrap();
+    let (signatures, _) =
+        Signature::gen_sigs(&tmp_in_file, 4, "rabin-karp:base=31", SignMode::Lean).unwrap();
+    assert_eq!(
+        signatures.header.algorithm,
+        "rabin-karp:base=31,modulus=4294967291"
+    );
+
+    // The seed is kept so the delta uses the same table
+    let (signatures, _) =
+        Signature::gen_sigs(&tmp_in_file, 4, "buzhash:seed=42", SignMode::Lean).unwrap();
+    assert_eq!(signatures.header.algorithm, "buzhash:seed=42");
+    let (signatures, _) = Signature::gen_sigs(&tmp_in_file, 4, "buzhash", SignMode::Lean).unwrap();
+    assert_eq!(
+        signatures.header.algorithm,
+        format!("buzhash:seed={}", DEFAULT_SEED)
+    );
+    remove_file(tmp_in_file).unwrap();
+}
+
+#[test]
+fn test_registered_algorithm() {
+    registry::register("byte_sum", |_| Ok(Box::new(ByteSum::default()))).unwrap();
+    assert!(registry::available().contains(&"byte_sum".to_string()));
+    for chunk_size in 2..=8 {
+        test_modify_add_data(chunk_size, "byte_sum");
+        test_modify_remove_data(chunk_size, "byte_sum");
+    }
+}
+
+#[test]
+fn test_unknown_algorithm() {
+    let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+    write(&tmp_in_file, "Typos are not silently replaced").unwrap();
+    let result = Signature::gen_sigs(&tmp_in_file, 4, "fletchr", SignMode::Lean);
+    assert!(matches!(result, Err(DiffError::UnknownAlgorithm(_))));
+    remove_file(tmp_in_file).unwrap();
+}
+
+#[test]
+fn test_missing_files() {
+    let missing = format!("{}_{}", TEST_IN_FILE, get_rnum());
+    let result = Signature::gen_sigs(&missing, 4, "adler", SignMode::Lean);
+    assert!(matches!(result, Err(DiffError::File { ref path, .. }) if *path == missing));
+
+    let result = patch_file_with_delta(missing.clone(), missing.clone(), missing.clone());
+    let err = result.err().unwrap();
+    assert!(err.to_string().starts_with(&missing));
+}
+
+#[test]
+fn test_cli_exit_codes() {
+    let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+    let tmp_sign_file = format!("{}_{}", TEST_SIGN_FILE, get_rnum());
+    let tmp_delta_file = format!("{}_{}", TEST_DELTA_FILE, get_rnum());
+    let tmp_out_file = format!("{}_{}", TEST_OUT_FILE, get_rnum());
+    write(&tmp_in_file, "Some text that gets signed").unwrap();
+
+    // Signatures with a block size signing never uses are corrupt
+    let (mut signatures, _) =
+        Signature::gen_sigs(&tmp_in_file, 4, "adler", SignMode::Lean).unwrap();
+    signatures.header.chunk_size = 0;
+    save_signatures(&tmp_sign_file, &signatures, Format::Binary).unwrap();
+    let args = [
+        "delta",
+        "--signature",
+        &tmp_sign_file,
+        "--modified",
+        &tmp_in_file,
+        "--output",
+        &tmp_delta_file,
+    ];
+    assert_eq!(run_cli(&args), Some(4));
+    // Positional files don't mix with saved signatures
+    let args = [
+        "delta",
+        &tmp_in_file,
+        &tmp_in_file,
+        "--signature",
+        &tmp_sign_file,
+    ];
+    assert_eq!(run_cli(&args), Some(2));
+
+    // A delta made against another basis
+    let args = ["sign", &tmp_in_file, "4", "adler", &tmp_sign_file];
+    assert_eq!(run_cli(&args), Some(0));
+    let args = ["delta-sig", &tmp_sign_file, &tmp_in_file, &tmp_delta_file];
+    assert_eq!(run_cli(&args), Some(0));
+    write(&tmp_in_file, "Some text that gets changed").unwrap();
+    let args = ["patch", &tmp_in_file, &tmp_delta_file, &tmp_out_file];
+    assert_eq!(run_cli(&args), Some(5));
+
+    // A missing input file
+    remove_file(&tmp_in_file).unwrap();
+    let args = ["sign", &tmp_in_file, "4", "adler", &tmp_sign_file];
+    assert_eq!(run_cli(&args), Some(3));
+
+    // Cleanup
+    remove_file(tmp_sign_file).unwrap();
+    remove_file(tmp_delta_file).unwrap();
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    /// Every step of the roll lands on the hash of the window it covers
+    #[test]
+    fn prop_rolling_hash_small_windows(data in vec(any::<u8>(), 2..512), size in 1usize..256) {
+        let size = size.min(data.len() - 1);
+        for kind in RollingHashKind::ALL {
+            let mut algo = kind.create();
+            algo.get_chunk_hash(&data[..size]).unwrap();
+            for (offset, byte) in data[size..].iter().enumerate() {
+                let hash = algo.get_rolling_hash(byte).unwrap();
+                let window = &data[offset + 1..offset + 1 + size];
+                prop_assert_eq!(hash, kind.create().get_chunk_hash(window).unwrap(), "{}", kind);
+            }
+        }
+    }
+
+    /// Windows up to 1 MiB, where the sums wrap around the modulus many times
+    #[test]
+    fn prop_rolling_hash_large_windows(seed: u64, size in 1usize..=1024 * 1024, steps in 1usize..64) {
+        let mut state = seed;
+        let data: Vec<u8> = (0..size + steps).map(|_| splitmix64(&mut state) as u8).collect();
+        for kind in RollingHashKind::ALL {
+            let mut algo = kind.create();
+            let mut hash = algo.get_chunk_hash(&data[..size]).unwrap();
+            for byte in &data[size..] {
+                hash = algo.get_rolling_hash(byte).unwrap();
+            }
+            prop_assert_eq!(hash, kind.create().get_chunk_hash(&data[steps..]).unwrap(), "{}", kind);
+        }
+    }
+}
+
+/// Rolling hash from outside the crate, the plain sum of the window
+#[derive(Default)]
+struct ByteSum {
+    sum: u32,
+    window: Vec<u8>,
+}
+
+impl Algorithm for ByteSum {
+    fn get_chunk_hash(&mut self, chunk: &[u8]) -> Result<u32, DiffError> {
+        self.window = chunk.to_vec();
+        self.sum = chunk.iter().map(|byte| *byte as u32).sum();
+        Ok(self.sum)
+    }
+
+    fn get_rolling_hash(&mut self, new_byte: &u8) -> Result<u32, DiffError> {
+        self.sum = self.sum + *new_byte as u32 - self.window.remove(0) as u32;
+        self.window.push(*new_byte);
+        Ok(self.sum)
+    }
+
+    fn get_current_hash(&self) -> Result<u32, DiffError> {
+        Ok(self.sum)
+    }
+
+    fn get_current_window(&self) -> Result<&[u8], DiffError> {
+        Ok(&self.window)
+    }
+}
+
+fn test_partial_blocks(chunk_size: usize, algorithm: &str) {
+    let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+    let tmp_delta_file = format!("{}_{}", TEST_DELTA_FILE, get_rnum());
+    let tmp_out_file = format!("{}_{}", TEST_OUT_FILE, get_rnum());
+
+    let data = "Mary had a little lamb, its fleece was white as snow!";
+    for len in [1, chunk_size - 1, chunk_size + 1, data.len()] {
+        if len % chunk_size == 0 {
+            continue;
+        }
+        let data = &data[..len];
+        write(&tmp_in_file, data).unwrap();
+        let (signatures, _) =
+            Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Lean).unwrap();
+
+        // An unchanged file is all copies, including the short last block
+        gen_delta_from_file(&tmp_in_file, &tmp_delta_file, Format::Binary, signatures).unwrap();
+        let delta = load_delta(&tmp_delta_file).unwrap();
+        assert!(delta
+            .delta
+            .iter()
+            .all(|op| matches!(op, Delta::Copy { .. })));
+
+        patch_file_with_delta(
+            tmp_in_file.clone(),
+            tmp_delta_file.clone(),
+            tmp_out_file.clone(),
+        )
+        .unwrap();
+        assert_eq!(read_to_string(&tmp_out_file).unwrap(), data);
+    }
+
+    // Cleanup
+    remove_file(tmp_in_file).unwrap();
+    remove_file(tmp_delta_file).unwrap();
+    remove_file(tmp_out_file).unwrap();
+}
+
+fn test_content_defined_chunking(
+    chunk_size: usize,
+    algorithm: &str,
+    strong_hash: &str,
+    format: Format,
+) {
+    let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+    let tmp_m_in_file = format!("{}_{}", TEST_M_IN_FILE, get_rnum());
+    let tmp_delta_file = format!("{}_{}", TEST_DELTA_FILE, get_rnum());
+    let tmp_out_file = format!("{}_{}", TEST_OUT_FILE, get_rnum());
+
+    let data = "Blocks cut by content find each other again after an edit moves them around.";
+    write(&tmp_in_file, data).unwrap();
+    let modified_data =
+        "Blocks cut by their content find each other after an edit moves them around!";
+    write(&tmp_m_in_file, modified_data).unwrap();
+
+    // Generate the signatures and delta
+    let (signatures, _) = Signature::gen_sigs_chunked(
+        File::open(&tmp_in_file).unwrap(),
+        chunk_size,
+        Chunking::gear(chunk_size),
+        algorithm,
+        strong_hash,
+        SignMode::Lean,
+    )
+    .unwrap();
+    gen_delta_from_file(&tmp_m_in_file, &tmp_delta_file, format, signatures).unwrap();
+    let delta = load_delta(&tmp_delta_file).unwrap();
+    assert_eq!(delta.header.chunking, Chunking::gear(chunk_size));
+
+    // Patch the file
+    patch_file_with_delta(
+        tmp_in_file.clone(),
+        tmp_delta_file.clone(),
+        tmp_out_file.clone(),
+    )
+    .unwrap();
+    let patched = read_to_string(&tmp_out_file).unwrap();
+    assert_eq!(patched, modified_data);
+
+    // Cleanup
+    remove_file(tmp_in_file).unwrap();
+    remove_file(tmp_m_in_file).unwrap();
+    remove_file(tmp_delta_file).unwrap();
+    remove_file(tmp_out_file).unwrap();
+}
+
+fn test_strong_hash(strong_hash: &str, checksum_len: usize) {
+    let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+    let tmp_m_in_file = format!("{}_{}", TEST_M_IN_FILE, get_rnum());
+    let tmp_delta_file = format!("{}_{}", TEST_DELTA_FILE, get_rnum());
+    let tmp_out_file = format!("{}_{}", TEST_OUT_FILE, get_rnum());
+
+    let data = "Every strong hash has to find the same blocks as the others.";
+    write(&tmp_in_file, data).unwrap();
+    let modified_data = "Every strong hash, cut short or not, has to find the same blocks.";
+    write(&tmp_m_in_file, modified_data).unwrap();
+
+    // The checksums are as long as asked and the hash is recorded
+    let (signatures, _) = Signature::gen_sigs_chunked(
+        File::open(&tmp_in_file).unwrap(),
+        4,
+        Chunking::Fixed,
+        "adler",
+        strong_hash,
+        SignMode::Lean,
+    )
+    .unwrap();
+    assert_eq!(signatures.header.strong_hash, strong_hash);
+    for sign in signatures.signatures.values().flatten() {
+        assert_eq!(sign.checksum.len(), checksum_len);
+    }
+
+    // Delta picks the hash up from the header
+    gen_delta_from_file(&tmp_m_in_file, &tmp_delta_file, Format::Binary, signatures).unwrap();
+    patch_file_with_delta(
+        tmp_in_file.clone(),
+        tmp_delta_file.clone(),
+        tmp_out_file.clone(),
+    )
+    .unwrap();
+    let patched = read_to_string(&tmp_out_file).unwrap();
+    assert_eq!(patched, modified_data);
+
+    // Cleanup
+    remove_file(tmp_in_file).unwrap();
+    remove_file(tmp_m_in_file).unwrap();
+    remove_file(tmp_delta_file).unwrap();
+    remove_file(tmp_out_file).unwrap();
+}
+
 fn test_modify_add_data(chunk_size: usize, algorithm: &str) {
     // Create the test files
     let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
@@ -52,18 +628,17 @@ fn test_modify_add_data(chunk_size: usize, algorithm: &str) {
     write(&tmp_m_in_file, modified_data).unwrap();
 
     // Generate the signatures and delta
-    let (signatures, _) = Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm).unwrap();
-    gen_delta_from_file(
-        &tmp_m_in_file,
-        chunk_size,
-        algorithm,
-        &tmp_delta_file,
-        signatures.clone(),
-    )
-    .unwrap();
+    let (signatures, _) =
+        Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Lean).unwrap();
+    gen_delta_from_file(&tmp_m_in_file, &tmp_delta_file, Format::Binary, signatures).unwrap();
 
     // Patch the file
-    patch_file_with_delta(tmp_delta_file.clone(), tmp_out_file.clone(), signatures).unwrap();
+    patch_file_with_delta(
+        tmp_in_file.clone(),
+        tmp_delta_file.clone(),
+        tmp_out_file.clone(),
+    )
+    .unwrap();
 
     // Verify the results
     let data = read_to_string(tmp_out_file.clone()).unwrap();
@@ -95,18 +670,73 @@ fn test_modify_remove_data(chunk_size: usize, algorithm: &str) {
     write(&tmp_m_in_file, modified_data).unwrap();
 
     // Generate the signatures and delta
-    let (signatures, _) = Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm).unwrap();
-    gen_delta_from_file(
-        &tmp_m_in_file,
-        chunk_size,
-        algorithm,
-        &tmp_delta_file,
-        signatures.clone(),
+    let (signatures, _) =
+        Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Lean).unwrap();
+    gen_delta_from_file(&tmp_m_in_file, &tmp_delta_file, Format::Binary, signatures).unwrap();
+
+    // Patch the file
+    patch_file_with_delta(
+        tmp_in_file.clone(),
+        tmp_delta_file.clone(),
+        tmp_out_file.clone(),
     )
     .unwrap();
 
+    // Verify the results
+    let data = read_to_string(tmp_out_file.clone()).unwrap();
+    assert_eq!(data, modified_data);
+
+    // Cleanup
+    remove_file(tmp_in_file).unwrap();
+    remove_file(tmp_m_in_file).unwrap();
+    remove_file(tmp_delta_file).unwrap();
+    remove_file(tmp_out_file).unwrap();
+}
+
+fn test_colliding_blocks(algorithm: &str) {
+    let chunk_size = 3;
+    // Create the test files
+    let tmp_in_file = format!("{}_{}", TEST_IN_FILE, get_rnum());
+    let tmp_m_in_file = format!("{}_{}", TEST_M_IN_FILE, get_rnum());
+    let tmp_delta_file = format!("{}_{}", TEST_DELTA_FILE, get_rnum());
+    let tmp_out_file = format!("{}_{}", TEST_OUT_FILE, get_rnum());
+
+    // Write some data
+    let data = "abcb`dxyz";
+    write(&tmp_in_file, data).unwrap();
+    let modified_data = "xyzb`dabc";
+    write(&tmp_m_in_file, modified_data).unwrap();
+
+    // Generate the signatures and delta
+    let (signatures, collisions) =
+        Signature::gen_sigs(&tmp_in_file, chunk_size, algorithm, SignMode::Lean).unwrap();
+    assert_eq!(collisions.colliding_blocks(), 2);
+    gen_delta_from_file(&tmp_m_in_file, &tmp_delta_file, Format::Binary, signatures).unwrap();
+    let delta = load_delta(&tmp_delta_file).unwrap();
+    // Every block is matched, nothing is sent as literal bytes
+    let expected = vec![
+        Delta::Copy {
+            start_block: 2,
+            block_count: 1,
+        },
+        Delta::Copy {
+            start_block: 1,
+            block_count: 1,
+        },
+        Delta::Copy {
+            start_block: 0,
+            block_count: 1,
+        },
+    ];
+    assert_eq!(delta.delta, expected);
+
     // Patch the file
-    patch_file_with_delta(tmp_delta_file.clone(), tmp_out_file.clone(), signatures).unwrap();
+    patch_file_with_delta(
+        tmp_in_file.clone(),
+        tmp_delta_file.clone(),
+        tmp_out_file.clone(),
+    )
+    .unwrap();
 
     // Verify the results
     let data = read_to_string(tmp_out_file.clone()).unwrap();
@@ -119,6 +749,17 @@ fn test_modify_remove_data(chunk_size: usize, algorithm: &str) {
     remove_file(tmp_out_file).unwrap();
 }
 
+/// Exit code of the rustdiff binary run with the arguments
+fn run_cli(args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_rustdiff"))
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap()
+        .code()
+}
+
 fn get_rnum() -> u32 {
     let mut rng = thread_rng();
     rng.gen()